@@ -62,16 +62,16 @@ impl<T> Array2D<T> {
     }
 
     pub fn coord_get(&self, i: usize, j: usize) -> Option<&T> {
-        match i * self.width * j < self.width * self.height {
+        match i < self.width && j < self.height {
             false => None,
-            true => Some(&self.data[i * self.width * j]),
+            true => Some(&self.data[i + j * self.width]),
         }
     }
 
     pub fn coord_get_mut(&mut self, i: usize, j: usize) -> Option<&mut T> {
-        match i * self.width * j < self.width * self.height {
+        match i < self.width && j < self.height {
             false => None,
-            true => Some(&mut self.data[i * self.width * j]),
+            true => Some(&mut self.data[i + j * self.width]),
         }
     }
 
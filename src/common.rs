@@ -1,4 +1,4 @@
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Direction {
     Up,
     Down,
@@ -7,6 +7,13 @@ pub enum Direction {
 }
 
 impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+    ];
+
     pub fn vec2f(self) -> Vec2f {
         match self {
             Direction::Up => Vec2(0.0, -1.0),
@@ -15,6 +22,67 @@ impl Direction {
             Direction::Right => Vec2(1.0, 0.0),
         }
     }
+
+    pub fn vec2i(self) -> Vec2i {
+        match self {
+            Direction::Up => Vec2(0, -1),
+            Direction::Down => Vec2(0, 1),
+            Direction::Left => Vec2(-1, 0),
+            Direction::Right => Vec2(1, 0),
+        }
+    }
+
+    pub fn opposite(self) -> Direction {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+}
+
+// xorshift64*, small and reproducible from a seed
+#[derive(Copy, Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        match seed ^ 0x9E37_79B9_7F4A_7C15 {
+            0 => Rng(1),
+            state => Rng(state),
+        }
+    }
+
+    // the raw state, for snapshots
+    pub fn state(&self) -> u64 {
+        self.0
+    }
+
+    pub fn from_state(state: u64) -> Self {
+        Rng(state.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // a number in 0..n
+    pub fn range(&mut self, n: u32) -> u32 {
+        ((self.next_u64() >> 32) % n as u64) as u32
+    }
+
+    pub fn direction(&mut self) -> Direction {
+        match self.range(4) {
+            0 => Direction::Up,
+            1 => Direction::Down,
+            2 => Direction::Left,
+            _ => Direction::Right,
+        }
+    }
 }
 
 pub fn clamp<T: PartialOrd>(low: T, t: T, high: T) -> T {
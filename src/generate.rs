@@ -0,0 +1,208 @@
+// Random levels. The same size and seed always give the same level: a border of Solid
+// walls, blocks scattered inside, and spawns that are all joined up by open floor.
+
+use crate::array2d::*;
+use crate::common::*;
+use crate::level::*;
+use crate::update::misc::*;
+use crossbeam_channel::Receiver;
+
+// how many of every 100 inner cells start with something in them
+const SOLID_CHANCE: u32 = 8;
+const NORMAL_CHANCE: u32 = 16;
+const ONE_WAY_CHANCE: u32 = 3;
+const TIME_BONUS_CHANCE: u32 = 2;
+
+// one enemy for about this many inner cells
+const CELLS_PER_ENEMY: usize = 30;
+
+// endless mode keeps to one screen
+pub const ENDLESS_WIDTH: usize = 10;
+pub const ENDLESS_HEIGHT: usize = 9;
+
+// sizes under 3 are made up to 3, so there's always room inside the walls
+pub fn generate(width: usize, height: usize, seed: u64) -> Level {
+    let (width, height) = (width.max(3), height.max(3));
+    let mut rng = Rng::new(seed);
+
+    // walls all the way around, and a scattering of blocks inside
+    let mut static_block_types = Array2D::from_closure(width, height, |i, j| {
+        match i == 0 || j == 0 || i == width - 1 || j == height - 1 {
+            true => Some(BlockType::Solid),
+            false => None,
+        }
+    });
+    for j in 1..height - 1 {
+        for i in 1..width - 1 {
+            let roll = rng.range(100);
+            static_block_types[(i, j)] = match roll {
+                _ if roll < SOLID_CHANCE => Some(BlockType::Solid),
+                _ if roll < SOLID_CHANCE + NORMAL_CHANCE => Some(BlockType::Normal),
+                _ if roll < SOLID_CHANCE + NORMAL_CHANCE + ONE_WAY_CHANCE => {
+                    Some(BlockType::OneWay(rng.direction()))
+                }
+                _ if roll < SOLID_CHANCE + NORMAL_CHANCE + ONE_WAY_CHANCE + TIME_BONUS_CHANCE => {
+                    Some(BlockType::TimeBonus)
+                }
+                _ => None,
+            };
+        }
+    }
+
+    // the player first, then the enemies, each on a cell of its own
+    let inner = (width - 2) * (height - 2);
+    let enemies = (inner / CELLS_PER_ENEMY).max(1).min(inner - 1);
+    let mut cells: Vec<(usize, usize)> = vec![];
+    while cells.len() < enemies + 1 {
+        let cell = (
+            1 + rng.range((width - 2) as u32) as usize,
+            1 + rng.range((height - 2) as u32) as usize,
+        );
+        if !cells.contains(&cell) {
+            cells.push(cell);
+        }
+    }
+
+    // spawns sit on open floor, with a way through to the player
+    for &cell in &cells {
+        static_block_types[cell] = None;
+    }
+    for &cell in &cells[1..] {
+        connect(&mut static_block_types, cells[0], cell, &mut rng);
+    }
+
+    let tanks = cells
+        .iter()
+        .enumerate()
+        .map(|(index, &(x, y))| TankSpawn {
+            x,
+            y,
+            direction: rng.direction(),
+            controller: match index {
+                0 => Controller::Player,
+                _ => enemy(&mut rng),
+            },
+        })
+        .collect();
+
+    Level {
+        time: DEFAULT_TIME,
+        lives: DEFAULT_LIVES,
+        hatch: vec![],
+        spawns: vec![],
+        waves: vec![],
+        terrain: Array2D::from_closure(width, height, |_, _| Terrain::Floor),
+        static_block_types,
+        tanks,
+        sliding_blocks: vec![],
+    }
+}
+
+fn enemy(rng: &mut Rng) -> Controller {
+    match rng.range(3) {
+        0 => Controller::Wander,
+        1 => Controller::Chase,
+        _ => {
+            let route = (0..4 + rng.range(5)).map(|_| rng.direction()).collect();
+            Controller::Patrol { route, step: 0 }
+        }
+    }
+}
+
+// cells a tank can drive through from any side
+fn open(block_type: Option<BlockType>) -> bool {
+    match block_type {
+        None | Some(BlockType::TimeBonus) => true,
+        _ => false,
+    }
+}
+
+// every cell reachable from a start through open cells
+fn reachable(grid: &Array2D<Option<BlockType>>, start: (usize, usize)) -> Array2D<bool> {
+    let (width, height) = (grid.get_width(), grid.get_height());
+    let mut seen = Array2D::from_closure(width, height, |i, j| (i, j) == start);
+    let mut stack = vec![start];
+    while let Some((i, j)) = stack.pop() {
+        for &dir in Direction::ALL.iter() {
+            let Vec2(dx, dy) = dir.vec2i();
+            let next = ((i as i32 + dx) as usize, (j as i32 + dy) as usize);
+            match grid.coord_get(next.0, next.1) {
+                Some(&block_type) if open(block_type) && !seen[next] => {
+                    seen[next] = true;
+                    stack.push(next);
+                }
+                _ => {}
+            }
+        }
+    }
+    seen
+}
+
+// clears a wandering path from one cell towards another until the two are joined up.
+// the border is never touched, since both ends are inside it and the path only closes in.
+fn connect(
+    grid: &mut Array2D<Option<BlockType>>,
+    from: (usize, usize),
+    to: (usize, usize),
+    rng: &mut Rng,
+) {
+    let mut cell = to;
+    while !reachable(grid, from)[to] {
+        // step along whichever axis still has distance to cover, picking at random when both do
+        let dx = from.0 as i32 - cell.0 as i32;
+        let dy = from.1 as i32 - cell.1 as i32;
+        let horizontal = match (dx, dy) {
+            (0, _) => false,
+            (_, 0) => true,
+            _ => rng.range(2) == 0,
+        };
+        cell = match horizontal {
+            true => ((cell.0 as i32 + dx.signum()) as usize, cell.1),
+            false => (cell.0, (cell.1 as i32 + dy.signum()) as usize),
+        };
+        if !open(grid[cell]) {
+            grid[cell] = None;
+        }
+    }
+}
+
+// random levels one after another, skipping any the solver can't beat by pushing alone.
+// they're found on a thread of their own, one level ahead, so the search never holds up
+// the game.
+pub struct Endless {
+    levels: Receiver<(u64, Level)>,
+}
+
+impl Endless {
+    pub fn new(seed: u64) -> Self {
+        let (levels_s, levels_r) = crossbeam_channel::bounded(1);
+        std::thread::spawn(move || {
+            let mut seed = seed;
+            loop {
+                let level = generate(ENDLESS_WIDTH, ENDLESS_HEIGHT, seed);
+                if crate::update::solver::solvable(&level) {
+                    // waits here until the game wants another, and stops once it's gone
+                    if levels_s.send((seed, level)).is_err() {
+                        break;
+                    }
+                }
+                seed = seed.wrapping_add(1);
+            }
+        });
+        Self { levels: levels_r }
+    }
+
+    // waits for the next level to be found
+    pub fn next_level(&mut self) -> Level {
+        let (seed, level) = self.levels.recv().unwrap();
+        println!("Level seed: {}", seed);
+        level
+    }
+
+    // the next level, if it's been found yet
+    pub fn try_next_level(&mut self) -> Option<Level> {
+        let (seed, level) = self.levels.try_recv().ok()?;
+        println!("Level seed: {}", seed);
+        Some(level)
+    }
+}
@@ -0,0 +1,719 @@
+// Levels are plain text. A header of one command per line comes first, followed
+// by the map itself. Anything after a ';' is a comment.
+//
+//   ; the first level
+//   time 66                  ; seconds on the clock
+//   lives 3                  ; including the one in play
+//   hatch 20 45              ; seconds in, when an egg breaks open
+//   tank 1 1 up player       ; cell x, cell y, facing, controller
+//   tank 3 3 left patrol uudd
+//   slide 1 3 right o        ; a block already sliding, using its map character
+//   spawn 3 1                ; cell x, cell y, where wave tanks come in
+//   wave 10 wander chase     ; seconds in, and the tanks it sends
+//   map 5 5                  ; width and height, followed by that many rows
+//   #####
+//   #...#
+//   #.o.#
+//   #...#
+//   #####
+//   terrain                  ; optional ground under the map, after it and the same size
+//   .....
+//   ..~~.
+//   .....
+//   .-TT.
+//   .....
+//
+// Map characters are listed in char_to_block, and terrain in char_to_terrain. Controllers are player, wander,
+// chase and patrol, which takes a route of u/d/l/r moves. Waves only send wander and chase tanks.
+// The edge of the map has to be solid wall all the way around, keeping everything inside.
+
+use crate::array2d::*;
+use crate::common::*;
+use crate::update::misc::*;
+use std::path::Path;
+
+pub const DEFAULT_TIME: u64 = 66; // seconds
+pub const DEFAULT_LIVES: u8 = 3;
+pub const MAX_TIME: u64 = u64::MAX / 1_000_000; // seconds, the most that fits in microseconds
+pub const MAX_MAP_CELLS: usize = 1_000_000; // far past anything playable, and safe to allocate
+
+pub fn on_border(width: usize, height: usize, (x, y): (usize, usize)) -> bool {
+    x == 0 || y == 0 || x == width - 1 || y == height - 1
+}
+
+// the first cell around the edge of a map that isn't solid wall
+pub fn border_gap(static_block_types: &Array2D<Option<BlockType>>) -> Option<(usize, usize)> {
+    let (width, height) = (
+        static_block_types.get_width(),
+        static_block_types.get_height(),
+    );
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .find(|&cell| {
+            on_border(width, height, cell) && static_block_types[cell] != Some(BlockType::Solid)
+        })
+}
+
+#[derive(Clone)]
+pub struct TankSpawn {
+    pub x: usize,
+    pub y: usize,
+    pub direction: Direction,
+    pub controller: Controller,
+}
+
+#[derive(Clone)]
+pub struct SlidingBlockSpawn {
+    pub x: usize,
+    pub y: usize,
+    pub direction: Direction,
+    pub block_type: BlockType,
+}
+
+// a batch of enemies coming in at the spawn points
+#[derive(Clone)]
+pub struct Wave {
+    pub time: u64, // seconds
+    pub tanks: Vec<Controller>,
+}
+
+pub struct Level {
+    pub time: u64, // seconds
+    pub lives: u8,
+    pub hatch: Vec<u64>, // seconds
+    pub spawns: Vec<(usize, usize)>,
+    pub waves: Vec<Wave>,
+    pub static_block_types: Array2D<Option<BlockType>>,
+    pub terrain: Array2D<Terrain>,
+    pub tanks: Vec<TankSpawn>,
+    pub sliding_blocks: Vec<SlidingBlockSpawn>,
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(std::io::Error),
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl std::fmt::Display for LevelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LevelError::Io(err) => write!(f, "{}", err),
+            LevelError::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "line {}, column {}: {}", line, column, message),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+fn error<T>(line: usize, column: usize, message: impl Into<String>) -> Result<T, LevelError> {
+    Err(LevelError::Parse {
+        line,
+        column,
+        message: message.into(),
+    })
+}
+
+pub fn char_to_block(c: char) -> Option<Option<BlockType>> {
+    match c {
+        '.' | ' ' => Some(None),
+        '#' => Some(Some(BlockType::Solid)),
+        'o' => Some(Some(BlockType::Normal)),
+        '^' => Some(Some(BlockType::OneWay(Direction::Up))),
+        'v' => Some(Some(BlockType::OneWay(Direction::Down))),
+        '<' => Some(Some(BlockType::OneWay(Direction::Left))),
+        '>' => Some(Some(BlockType::OneWay(Direction::Right))),
+        '+' => Some(Some(BlockType::TimeBonus)),
+        '*' => Some(Some(BlockType::Diamond)),
+        '@' => Some(Some(BlockType::Egg)),
+        '%' => Some(Some(BlockType::Brick(BRICK_WHOLE))),
+        'B' => Some(Some(BlockType::Base)),
+        // bricks with pieces missing are the hex digit of what's left
+        c => match c.to_digit(16) {
+            Some(mask @ 1..=14) if !c.is_ascii_uppercase() => {
+                Some(Some(BlockType::Brick(mask as u8)))
+            }
+            _ => None,
+        },
+    }
+}
+
+pub fn block_to_char(block_type: Option<BlockType>) -> char {
+    match block_type {
+        None => '.',
+        Some(BlockType::Solid) => '#',
+        Some(BlockType::Normal) => 'o',
+        Some(BlockType::OneWay(Direction::Up)) => '^',
+        Some(BlockType::OneWay(Direction::Down)) => 'v',
+        Some(BlockType::OneWay(Direction::Left)) => '<',
+        Some(BlockType::OneWay(Direction::Right)) => '>',
+        Some(BlockType::TimeBonus) => '+',
+        Some(BlockType::Diamond) => '*',
+        Some(BlockType::Egg) => '@',
+        Some(BlockType::Base) => 'B',
+        Some(BlockType::Brick(BRICK_WHOLE)) => '%',
+        Some(BlockType::Brick(mask)) => std::char::from_digit(mask as u32, 16).unwrap_or('%'),
+    }
+}
+
+pub fn char_to_terrain(c: char) -> Option<Terrain> {
+    match c {
+        '.' | ' ' => Some(Terrain::Floor),
+        '~' => Some(Terrain::Water),
+        '-' => Some(Terrain::Ice),
+        'T' => Some(Terrain::Trees),
+        _ => None,
+    }
+}
+
+pub fn terrain_to_char(terrain: Terrain) -> char {
+    match terrain {
+        Terrain::Floor => '.',
+        Terrain::Water => '~',
+        Terrain::Ice => '-',
+        Terrain::Trees => 'T',
+    }
+}
+
+pub fn parse_direction(s: &str) -> Option<Direction> {
+    match s {
+        "up" | "u" => Some(Direction::Up),
+        "down" | "d" => Some(Direction::Down),
+        "left" | "l" => Some(Direction::Left),
+        "right" | "r" => Some(Direction::Right),
+        _ => None,
+    }
+}
+
+pub fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Up => "up",
+        Direction::Down => "down",
+        Direction::Left => "left",
+        Direction::Right => "right",
+    }
+}
+
+// splits a line into words, along with the 1 based column each starts at
+fn words(line: &str) -> Vec<(usize, &str)> {
+    let line = match line.find(';') {
+        Some(index) => &line[..index],
+        None => line,
+    };
+
+    let mut words = vec![];
+    let mut start = None;
+    for (index, c) in line.char_indices().chain(Some((line.len(), ' '))) {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(index),
+            (true, Some(s)) => {
+                words.push((line[..s].chars().count() + 1, &line[s..index]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    words
+}
+
+// the rows of a map or terrain follow their command directly, with short rows padded
+// out with the default
+fn rows<'a, T: Clone>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    line: usize,
+    width: usize,
+    height: usize,
+    what: &str,
+    default: T,
+    parse: impl Fn(char) -> Option<T>,
+) -> Result<Vec<T>, LevelError> {
+    let mut data = Vec::with_capacity(width * height);
+    for row in 0..height {
+        let (line, text) = match lines.next() {
+            Some(next) => next,
+            None => {
+                return error(
+                    line + row + 1,
+                    1,
+                    format!("expected {} {} rows, found {}", height, what, row),
+                )
+            }
+        };
+        let text = text.trim_end();
+        let mut count = 0;
+        for (index, c) in text.chars().enumerate() {
+            if index >= width {
+                return error(
+                    line,
+                    index + 1,
+                    format!("{} row is longer than {} cells", what, width),
+                );
+            }
+            match parse(c) {
+                Some(cell) => data.push(cell),
+                None => {
+                    return error(
+                        line,
+                        index + 1,
+                        format!("unknown {} character '{}'", what, c),
+                    )
+                }
+            }
+            count += 1;
+        }
+        data.extend((count..width).map(|_| default.clone()));
+    }
+    Ok(data)
+}
+
+// writes the level back out in the same format parse reads
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "time {}", self.time)?;
+        writeln!(f, "lives {}", self.lives)?;
+        if !self.hatch.is_empty() {
+            let times: Vec<String> = self.hatch.iter().map(|time| time.to_string()).collect();
+            writeln!(f, "hatch {}", times.join(" "))?;
+        }
+
+        for tank in &self.tanks {
+            let direction = direction_name(tank.direction);
+            write!(f, "tank {} {} {} ", tank.x, tank.y, direction)?;
+            match &tank.controller {
+                Controller::Player => writeln!(f, "player")?,
+                Controller::Wander => writeln!(f, "wander")?,
+                Controller::Chase => writeln!(f, "chase")?,
+                Controller::Patrol { route, .. } => {
+                    let route: String = route
+                        .iter()
+                        .map(|&dir| direction_name(dir).chars().next().unwrap())
+                        .collect();
+                    writeln!(f, "patrol {}", route)?
+                }
+            }
+        }
+
+        for &(x, y) in &self.spawns {
+            writeln!(f, "spawn {} {}", x, y)?;
+        }
+        for wave in &self.waves {
+            write!(f, "wave {}", wave.time)?;
+            for controller in &wave.tanks {
+                match controller {
+                    Controller::Chase => write!(f, " chase")?,
+                    _ => write!(f, " wander")?,
+                }
+            }
+            writeln!(f)?;
+        }
+
+        for block in &self.sliding_blocks {
+            writeln!(
+                f,
+                "slide {} {} {} {}",
+                block.x,
+                block.y,
+                direction_name(block.direction),
+                block_to_char(Some(block.block_type))
+            )?;
+        }
+
+        let width = self.static_block_types.get_width();
+        let height = self.static_block_types.get_height();
+        writeln!(f, "map {} {}", width, height)?;
+        for y in 0..height {
+            let row: String = (0..width)
+                .map(|x| block_to_char(self.static_block_types[(x, y)]))
+                .collect();
+            writeln!(f, "{}", row)?;
+        }
+
+        let mut bare = true;
+        self.terrain
+            .for_each(|_, _, &terrain| bare &= terrain == Terrain::Floor);
+        if !bare {
+            writeln!(f, "terrain")?;
+            for y in 0..height {
+                let row: String = (0..width)
+                    .map(|x| terrain_to_char(self.terrain[(x, y)]))
+                    .collect();
+                writeln!(f, "{}", row)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Level {
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+
+    // loads either one of our own levels, or a Tiled map going by the extension
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("tmx") | Some("json") | Some("tmj") => Ok(crate::tiled::load(path)?),
+            _ => Ok(Self::load(path)?),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LevelError> {
+        let src = std::fs::read_to_string(path).map_err(LevelError::Io)?;
+        Self::parse(&src)
+    }
+
+    pub fn parse(src: &str) -> Result<Self, LevelError> {
+        let mut time = DEFAULT_TIME;
+        let mut lives = DEFAULT_LIVES;
+        let mut hatch = vec![];
+        let mut spawns = vec![];
+        let mut waves = vec![];
+        let mut static_block_types = None;
+        let mut terrain = None;
+        let mut tanks = vec![];
+        let mut sliding_blocks = vec![];
+
+        // (line, column) of each spawn, for errors found once the map is known
+        let mut tank_locations = vec![];
+        let mut sliding_block_locations = vec![];
+        let mut spawn_locations = vec![];
+        let mut wave_location = None;
+
+        let mut lines = src
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line));
+        while let Some((line, text)) = lines.next() {
+            let words = words(text);
+            let (column, command) = match words.first() {
+                Some(&word) => word,
+                None => continue,
+            };
+            let args = &words[1..];
+
+            // pulls out the nth argument, parsed
+            let arg = |n: usize, what: &str| -> Result<(usize, &str), LevelError> {
+                match args.get(n) {
+                    Some(&arg) => Ok(arg),
+                    None => error(line, text.chars().count() + 1, format!("expected {}", what)),
+                }
+            };
+            let number = |n: usize, what: &str| -> Result<u64, LevelError> {
+                let (column, word) = arg(n, what)?;
+                match word.parse() {
+                    Ok(number) => Ok(number),
+                    Err(_) => error(
+                        line,
+                        column,
+                        format!("expected {}, found \"{}\"", what, word),
+                    ),
+                }
+            };
+            let seconds = |n: usize| -> Result<u64, LevelError> {
+                let (column, _) = arg(n, "a time in seconds")?;
+                match number(n, "a time in seconds")? {
+                    time if time <= MAX_TIME => Ok(time),
+                    _ => error(
+                        line,
+                        column,
+                        format!("times can't be more than {} seconds", MAX_TIME),
+                    ),
+                }
+            };
+            let direction = |n: usize| -> Result<Direction, LevelError> {
+                let (column, word) = arg(n, "a direction")?;
+                match parse_direction(word) {
+                    Some(direction) => Ok(direction),
+                    None => error(line, column, format!("unknown direction \"{}\"", word)),
+                }
+            };
+            let no_more = |n: usize| -> Result<(), LevelError> {
+                match args.get(n) {
+                    Some(&(column, word)) => {
+                        error(line, column, format!("unexpected \"{}\"", word))
+                    }
+                    None => Ok(()),
+                }
+            };
+
+            match command {
+                "time" => {
+                    time = seconds(0)?;
+                    no_more(1)?;
+                }
+
+                "lives" => {
+                    let (column, _) = arg(0, "a number of lives")?;
+                    lives = match number(0, "a number of lives")? {
+                        n @ 1..=255 => n as u8,
+                        _ => return error(line, column, "lives must be between 1 and 255"),
+                    };
+                    no_more(1)?;
+                }
+
+                "hatch" => {
+                    arg(0, "a time in seconds")?;
+                    for n in 0..args.len() {
+                        hatch.push(seconds(n)?);
+                    }
+                    hatch.sort();
+                }
+
+                "tank" => {
+                    let x = number(0, "a cell x")? as usize;
+                    let y = number(1, "a cell y")? as usize;
+                    let direction = direction(2)?;
+                    let (kind_column, kind) = arg(3, "a controller")?;
+                    let controller = match kind {
+                        "player" => Controller::Player,
+                        "wander" => Controller::Wander,
+                        "chase" => Controller::Chase,
+                        "patrol" => {
+                            let (route_column, route_word) = arg(4, "a patrol route")?;
+                            let mut route = vec![];
+                            for (offset, c) in route_word.chars().enumerate() {
+                                match parse_direction(&c.to_string()) {
+                                    Some(direction) => route.push(direction),
+                                    None => {
+                                        return error(
+                                            line,
+                                            route_column + offset,
+                                            format!("unknown patrol move '{}'", c),
+                                        )
+                                    }
+                                }
+                            }
+                            Controller::Patrol { route, step: 0 }
+                        }
+                        _ => {
+                            return error(
+                                line,
+                                kind_column,
+                                format!("unknown controller \"{}\"", kind),
+                            )
+                        }
+                    };
+                    no_more(match controller {
+                        Controller::Patrol { .. } => 5,
+                        _ => 4,
+                    })?;
+
+                    tanks.push(TankSpawn {
+                        x,
+                        y,
+                        direction,
+                        controller,
+                    });
+                    tank_locations.push((line, column));
+                }
+
+                "spawn" => {
+                    let x = number(0, "a cell x")? as usize;
+                    let y = number(1, "a cell y")? as usize;
+                    no_more(2)?;
+                    spawns.push((x, y));
+                    spawn_locations.push((line, column));
+                }
+
+                "wave" => {
+                    let time = seconds(0)?;
+                    arg(1, "a controller")?;
+                    let mut tanks = vec![];
+                    for &(kind_column, kind) in &args[1..] {
+                        tanks.push(match kind {
+                            "wander" => Controller::Wander,
+                            "chase" => Controller::Chase,
+                            _ => {
+                                return error(
+                                    line,
+                                    kind_column,
+                                    format!("waves send wander or chase tanks, not \"{}\"", kind),
+                                )
+                            }
+                        });
+                    }
+                    waves.push(Wave { time, tanks });
+                    wave_location.get_or_insert((line, column));
+                }
+
+                "slide" => {
+                    let x = number(0, "a cell x")? as usize;
+                    let y = number(1, "a cell y")? as usize;
+                    let direction = direction(2)?;
+                    let (block_column, block_word) = arg(3, "a block character")?;
+                    let mut chars = block_word.chars();
+                    let block_type = match (chars.next().and_then(char_to_block), chars.next()) {
+                        (Some(Some(block_type)), None) => block_type,
+                        _ => {
+                            return error(
+                                line,
+                                block_column,
+                                format!("\"{}\" is not a block character", block_word),
+                            )
+                        }
+                    };
+                    no_more(4)?;
+
+                    sliding_blocks.push(SlidingBlockSpawn {
+                        x,
+                        y,
+                        direction,
+                        block_type,
+                    });
+                    sliding_block_locations.push((line, column));
+                }
+
+                "map" => {
+                    if static_block_types.is_some() {
+                        return error(line, column, "the map is already defined");
+                    }
+                    let width = number(0, "a map width")? as usize;
+                    let height = number(1, "a map height")? as usize;
+                    no_more(2)?;
+                    if width == 0 || height == 0 {
+                        return error(line, column, "the map can't be empty");
+                    }
+                    match width.checked_mul(height) {
+                        Some(cells) if cells <= MAX_MAP_CELLS => {}
+                        _ => {
+                            return error(
+                                line,
+                                column,
+                                format!("the map can't be more than {} cells", MAX_MAP_CELLS),
+                            )
+                        }
+                    }
+
+                    let data = rows(&mut lines, line, width, height, "map", None, char_to_block)?;
+                    let map = Array2D::from_box(width, height, data.into_boxed_slice());
+                    if let Some((x, y)) = border_gap(&map) {
+                        return error(
+                            line + 1 + y,
+                            x + 1,
+                            "the edge of the map has to be solid wall",
+                        );
+                    }
+                    static_block_types = Some(map);
+                }
+
+                "terrain" => {
+                    if terrain.is_some() {
+                        return error(line, column, "the terrain is already defined");
+                    }
+                    let (width, height) = match &static_block_types {
+                        Some(map) => (map.get_width(), map.get_height()),
+                        None => {
+                            return error(line, column, "the terrain has to come after the map")
+                        }
+                    };
+                    no_more(0)?;
+
+                    let data = rows(
+                        &mut lines,
+                        line,
+                        width,
+                        height,
+                        "terrain",
+                        Terrain::Floor,
+                        char_to_terrain,
+                    )?;
+                    terrain = Some(Array2D::from_box(width, height, data.into_boxed_slice()));
+                }
+
+                _ => return error(line, column, format!("unknown command \"{}\"", command)),
+            }
+        }
+
+        // everything else needs the map to check against
+        let static_block_types = match static_block_types {
+            Some(static_block_types) => static_block_types,
+            None => return error(src.lines().count() + 1, 1, "the level has no map"),
+        };
+        let terrain = terrain.unwrap_or_else(|| {
+            Array2D::from_closure(
+                static_block_types.get_width(),
+                static_block_types.get_height(),
+                |_, _| Terrain::Floor,
+            )
+        });
+        let on_empty_cell = |x: usize, y: usize| match static_block_types.coord_get(x, y) {
+            Some(None) => true,
+            _ => false,
+        };
+
+        for (tank, &(line, column)) in tanks.iter().zip(tank_locations.iter()) {
+            if !on_empty_cell(tank.x, tank.y) {
+                return error(line, column, "tanks must start on an empty cell of the map");
+            }
+        }
+        for (&(x, y), &(line, column)) in spawns.iter().zip(spawn_locations.iter()) {
+            if !on_empty_cell(x, y) {
+                return error(
+                    line,
+                    column,
+                    "spawn points must be on an empty cell of the map",
+                );
+            }
+        }
+        match wave_location {
+            Some((line, column)) if spawns.is_empty() => {
+                return error(line, column, "waves need a spawn point to come in at")
+            }
+            _ => {}
+        }
+        waves.sort_by_key(|wave| wave.time);
+
+        for (block, &(line, column)) in sliding_blocks.iter().zip(sliding_block_locations.iter()) {
+            if !on_empty_cell(block.x, block.y) {
+                return error(
+                    line,
+                    column,
+                    "sliding blocks must start on an empty cell of the map",
+                );
+            }
+        }
+
+        // the player needs exactly one place to (re)spawn
+        let players =
+            tanks
+                .iter()
+                .zip(tank_locations.iter())
+                .filter(|(tank, _)| match tank.controller {
+                    Controller::Player => true,
+                    _ => false,
+                });
+        match players
+            .map(|(_, &location)| location)
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            [_] => {}
+            [] => return error(src.lines().count() + 1, 1, "the level has no player tank"),
+            [_, (line, column), ..] => {
+                return error(*line, *column, "the level has more than one player tank")
+            }
+        }
+
+        Ok(Self {
+            time,
+            lives,
+            hatch,
+            spawns,
+            waves,
+            static_block_types,
+            terrain,
+            tanks,
+            sliding_blocks,
+        })
+    }
+}
@@ -6,12 +6,17 @@ extern crate crossbeam_channel;
 extern crate ezgl;
 extern crate glutin;
 extern crate nalgebra;
+extern crate roxmltree;
+extern crate serde_json;
 
 #[macro_use]
 mod render;
 mod array2d;
 mod common;
+mod generate;
 mod io;
+mod level;
+mod tiled;
 mod time;
 mod update;
 
@@ -19,6 +24,64 @@ use glutin::event_loop::EventLoop;
 use glutin::window::WindowBuilder;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // run without a window instead
+    if let Some("--headless") = args.first().map(|arg| arg.as_str()) {
+        headless(&args[1..]);
+        return;
+    }
+
+    // check levels can be beaten, then quit
+    if let Some("--solve") = args.first().map(|arg| arg.as_str()) {
+        solve(&args[1..]);
+        return;
+    }
+
+    // load the level named on the command line, or the first one,
+    // or take both the level and the input from a replay,
+    // or make up levels as we go
+    let (level, input, endless) = match args.as_slice() {
+        [flag, path] if flag == "--replay" => {
+            let replay = crate::update::replay::Replay::open(path).unwrap_or_else(|err| {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            });
+            let level = crate::level::Level::parse(&replay.level).unwrap_or_else(|err| {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            });
+            (level, crate::update::Input::Playback(replay), None)
+        }
+        [flag] if flag == "--random" => random(0),
+        [flag, seed] if flag == "--random" => match seed.parse() {
+            Ok(seed) => random(seed),
+            Err(_) => {
+                eprintln!("\"{}\" is not a seed", seed);
+                std::process::exit(2);
+            }
+        },
+        [flag, path] if flag == "--record" => (
+            load_level(None),
+            crate::update::Input::Record(path.into()),
+            None,
+        ),
+        [level, flag, path] if flag == "--record" => (
+            load_level(Some(level)),
+            crate::update::Input::Record(path.into()),
+            None,
+        ),
+        [] | [_] => (load_level(args.first()), crate::update::Input::Live, None),
+        _ => {
+            eprintln!("usage: battle-tank [level] [--record <replay>]");
+            eprintln!("       battle-tank --replay <replay>");
+            eprintln!("       battle-tank --random [seed]");
+            eprintln!("       battle-tank --headless <level> <ticks> [script]");
+            eprintln!("       battle-tank --solve [level...]");
+            std::process::exit(2);
+        }
+    };
+
     // widow parameters
     let window_w = 160f64 * 5.;
     let window_h = 144f64 * 5.;
@@ -46,8 +109,20 @@ fn main() {
     // sender-receiver pair for input -> update messages
     let (input_s, input_r) = crossbeam_channel::unbounded();
 
+    // update needs the window size up front to map the cursor into the world
+    let size = window.window().inner_size();
+    #[allow(unused_must_use)]
+    {
+        input_s.send(crate::update::event::Event::Resize(
+            size.width as f32,
+            size.height as f32,
+        ));
+    }
+
     // update thread
-    std::thread::spawn(move || crate::update::update_thread(render_s, input_r));
+    std::thread::spawn(move || {
+        crate::update::update_thread(level, input, endless, render_s, input_r)
+    });
 
     // render thread
     std::thread::spawn(move || crate::render::render_thread(window, render_r));
@@ -73,6 +148,112 @@ fn main() {
     });
 }
 
+fn random(
+    seed: u64,
+) -> (
+    crate::level::Level,
+    crate::update::Input,
+    Option<crate::generate::Endless>,
+) {
+    let mut endless = crate::generate::Endless::new(seed);
+    let level = endless.next_level();
+    (level, crate::update::Input::Live, Some(endless))
+}
+
+fn load_level(path: Option<&String>) -> crate::level::Level {
+    let path = match path {
+        Some(path) => std::path::PathBuf::from(path),
+        None => crate::io::get_root().join("resources/levels/01.lvl"),
+    };
+    match crate::level::Level::open(&path) {
+        Ok(level) => level,
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+    }
+}
+
+// battle-tank --headless <level> <ticks> [script]
+fn headless(args: &[String]) {
+    let usage = || -> ! {
+        eprintln!("usage: battle-tank --headless <level> <ticks> [script]");
+        std::process::exit(2);
+    };
+
+    let level = load_level(Some(args.get(0).unwrap_or_else(|| usage())));
+    let ticks = match args.get(1).map(|ticks| ticks.parse()) {
+        Some(Ok(ticks)) => ticks,
+        _ => usage(),
+    };
+    let script = match args.get(2) {
+        Some(path) => {
+            let src = std::fs::read_to_string(path).unwrap_or_else(|err| {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            });
+            crate::update::parse_script(&src).unwrap_or_else(|err| {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            })
+        }
+        None => vec![],
+    };
+
+    print!("{}", crate::update::run_headless(&level, &script, ticks));
+}
+
+// battle-tank --solve [level...], which defaults to every shipped level
+fn solve(args: &[String]) {
+    let paths = match args {
+        [] => level_files(&crate::io::get_root().join("resources/levels")),
+        paths => paths.iter().map(std::path::PathBuf::from).collect(),
+    };
+
+    let mut failed = false;
+    for path in paths {
+        let level = match crate::level::Level::open(&path) {
+            Ok(level) => level,
+            Err(err) => {
+                println!("{}: {}", path.display(), err);
+                failed = true;
+                continue;
+            }
+        };
+
+        let report = crate::update::solver::solve(&level);
+        print!("{}: {}", path.display(), report);
+        failed |= !report.ok();
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn level_files(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+    let mut paths = vec![];
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return paths,
+    };
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        if path.is_dir() {
+            paths.extend(level_files(&path));
+            continue;
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("lvl") | Some("tmx") | Some("tmj") | Some("json") => paths.push(path),
+            _ => {}
+        }
+    }
+    paths.sort();
+    paths
+}
+
 fn map_event(event: glutin::event::Event<()>) -> Option<crate::update::event::Event> {
     use crate::update::event::*;
     use glutin::dpi::PhysicalPosition;
@@ -90,11 +271,15 @@ fn map_event(event: glutin::event::Event<()>) -> Option<crate::update::event::Ev
                 ..
             } => return Some(Event::MouseMove(x as f32, y as f32)),
 
+            WindowEvent::Resized(size) => {
+                return Some(Event::Resize(size.width as f32, size.height as f32))
+            }
+
             WindowEvent::MouseInput { state, button, .. } => {
                 use glutin::event::MouseButton;
                 let button = match button {
-                    MouseButton::Left => Key::Rmb,
-                    MouseButton::Right => Key::Lmb,
+                    MouseButton::Left => Key::Lmb,
+                    MouseButton::Right => Key::Rmb,
                     _ => return None,
                 };
 
@@ -145,6 +330,8 @@ fn map_event(event: glutin::event::Event<()>) -> Option<crate::update::event::Ev
                     VirtualKeyCode::Down => Key::Down,
                     VirtualKeyCode::Left => Key::Left,
                     VirtualKeyCode::Right => Key::Right,
+                    VirtualKeyCode::F5 => Key::F5,
+                    VirtualKeyCode::F9 => Key::F9,
                     _ => return None,
                 };
 
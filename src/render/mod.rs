@@ -2,6 +2,7 @@ mod render_io;
 pub mod render_state;
 
 use crate::common::*;
+use crate::update::misc::{BlockType, GameState, TankState, Terrain};
 use crossbeam_channel::Receiver;
 use glutin::window::Window;
 use glutin::ContextWrapper;
@@ -22,11 +23,26 @@ pub fn render_thread(window: ContextWrapper<NotCurrent, Window>, render_r: Recei
     let textures = render_io::load_textures_from("resources/textures");
     let programs = render_io::load_programs_from("resources/shaders");
 
-    // some constants
-    const MAX_TANKS: usize = 5;
+    // some constants. waves and eggs can bring in any number of tanks, so anything
+    // past these is left undrawn rather than run off the end of the buffers
+    const MAX_TANKS: usize = 32;
     const MAX_EXPLOSIONS: usize = MAX_TANKS;
     const MAX_BLOCKS: usize = (160 / 16 + 1) * (144 / 16 + 1);
-    const MAX_SPRITES: usize = MAX_TANKS + MAX_EXPLOSIONS + MAX_BLOCKS;
+    const MAX_SLIDING_BLOCKS: usize = 32;
+    const MAX_PROJECTILES: usize = MAX_TANKS * 2;
+    const MAX_CURSORS: usize = 1;
+    const MAX_HUD_TANKS: usize = 16;
+    const MAX_BRICK_PIECES: usize = MAX_BLOCKS * 3; // bricks take up to 4 sprites
+    const MAX_TERRAIN: usize = MAX_BLOCKS;
+    const MAX_SPRITES: usize = MAX_TANKS
+        + MAX_EXPLOSIONS
+        + MAX_BLOCKS
+        + MAX_SLIDING_BLOCKS
+        + MAX_BRICK_PIECES
+        + MAX_TERRAIN
+        + MAX_PROJECTILES
+        + MAX_CURSORS
+        + MAX_HUD_TANKS;
 
     // all the sprite data gets dumped into these things
     let mut xy_data: Box<[(f32, f32)]> = vec![<_>::default(); MAX_SPRITES * 4].into_boxed_slice();
@@ -46,8 +62,11 @@ pub fn render_thread(window: ContextWrapper<NotCurrent, Window>, render_r: Recei
         ),
     );
 
+    // the score goes in the title bar, which only needs setting when it changes
+    let mut shown_score = None;
+
     // process frames sent from update
-    for (_i, frame) in render_r.iter().enumerate() {
+    for (frame_index, frame) in render_r.iter().enumerate() {
         if frame.exit {
             break;
         }
@@ -55,10 +74,59 @@ pub fn render_thread(window: ContextWrapper<NotCurrent, Window>, render_r: Recei
         // the sprite counter is used to keep track of the top of the xy/uv arrays
         let mut sprite_counter = 0usize;
 
+        // the ground, under everything else
+        frame.terrain.for_each(|rel_x, rel_y, &terrain| {
+            let uv = match terrain {
+                Terrain::Trees => None,
+                _ => crate::update::misc::terrain_to_uv(terrain),
+            };
+            if let Some(Vec2(u, v)) = uv {
+                let x = frame.static_blocks_offset.0 + (rel_x * 16) as f32;
+                let y = frame.static_blocks_offset.1 + (rel_y * 16) as f32;
+                xy_data[sprite_counter * 4 + 0] = (x + 0., y + 0.);
+                xy_data[sprite_counter * 4 + 1] = (x + 16., y + 0.);
+                xy_data[sprite_counter * 4 + 2] = (x + 16., y + 16.);
+                xy_data[sprite_counter * 4 + 3] = (x + 0., y + 16.);
+
+                uv_data[sprite_counter * 4 + 0] = (u + 0. + 0.05, v + 0. + 0.05);
+                uv_data[sprite_counter * 4 + 1] = (u + 16. - 0.05, v + 0. + 0.05);
+                uv_data[sprite_counter * 4 + 2] = (u + 16. - 0.05, v + 16. - 0.05);
+                uv_data[sprite_counter * 4 + 3] = (u + 0. + 0.05, v + 16. - 0.05);
+
+                sprite_counter += 1;
+            }
+        });
+
         // static blocks
         frame
             .static_block_types
             .for_each(|rel_x, rel_y, block_type_opt| {
+                // bricks are drawn a quarter at a time, leaving out the broken ones
+                if let Some(BlockType::Brick(mask)) = *block_type_opt {
+                    let x = frame.static_blocks_offset.0 + (rel_x * 16) as f32;
+                    let y = frame.static_blocks_offset.1 + (rel_y * 16) as f32;
+                    let Vec2(u, v) = crate::update::misc::block_to_uv(BlockType::Brick(mask));
+                    for quarter in 0..4 {
+                        if mask & (1 << quarter) == 0 {
+                            continue;
+                        }
+                        let (qx, qy) = ((quarter % 2 * 8) as f32, (quarter / 2 * 8) as f32);
+                        let (x, y, u, v) = (x + qx, y + qy, u + qx, v + qy);
+                        xy_data[sprite_counter * 4 + 0] = (x + 0., y + 0.);
+                        xy_data[sprite_counter * 4 + 1] = (x + 8., y + 0.);
+                        xy_data[sprite_counter * 4 + 2] = (x + 8., y + 8.);
+                        xy_data[sprite_counter * 4 + 3] = (x + 0., y + 8.);
+
+                        uv_data[sprite_counter * 4 + 0] = (u + 0. + 0.05, v + 0. + 0.05);
+                        uv_data[sprite_counter * 4 + 1] = (u + 8. - 0.05, v + 0. + 0.05);
+                        uv_data[sprite_counter * 4 + 2] = (u + 8. - 0.05, v + 8. - 0.05);
+                        uv_data[sprite_counter * 4 + 3] = (u + 0. + 0.05, v + 8. - 0.05);
+
+                        sprite_counter += 1;
+                    }
+                    return;
+                }
+
                 if let Some(block_type) = block_type_opt {
                     // fill xy data for static blocks
                     let x = frame.static_blocks_offset.0 + (rel_x * 16) as f32;
@@ -81,7 +149,7 @@ pub fn render_thread(window: ContextWrapper<NotCurrent, Window>, render_r: Recei
             });
 
         // sliding blocks
-        for index in 0..frame.sliding_block_positions.len() {
+        for index in 0..frame.sliding_block_positions.len().min(MAX_SLIDING_BLOCKS) {
             // xy
             let x = frame.sliding_block_positions[index].0;
             let y = frame.sliding_block_positions[index].1;
@@ -102,7 +170,12 @@ pub fn render_thread(window: ContextWrapper<NotCurrent, Window>, render_r: Recei
         }
 
         // tanks
-        for index in 0..frame.tank_positions.len() {
+        for index in 0..frame.tank_positions.len().min(MAX_TANKS) {
+            // shielded tanks blink
+            if frame.tank_shielded[index] && frame_index / 4 % 2 == 1 {
+                continue;
+            }
+
             // fill xy data for tanks
             let x = frame.tank_positions[index].0;
             let y = frame.tank_positions[index].1;
@@ -112,7 +185,7 @@ pub fn render_thread(window: ContextWrapper<NotCurrent, Window>, render_r: Recei
             xy_data[sprite_counter * 4 + 3] = (x + 0., y + 16.);
 
             // fill uv data for tanks
-            let Vec2(u, v) = Vec2(32., 16.); // tank_to_uv
+            let Vec2(u, v) = crate::update::misc::tank_to_uv(frame.tank_states[index]);
             uv_data[sprite_counter * 4 + 0] = (u + 0. + 0.05, v + 0. + 0.05);
             uv_data[sprite_counter * 4 + 1] = (u + 16. - 0.05, v + 0. + 0.05);
             uv_data[sprite_counter * 4 + 2] = (u + 16. - 0.05, v + 16. - 0.05);
@@ -122,20 +195,96 @@ pub fn render_thread(window: ContextWrapper<NotCurrent, Window>, render_r: Recei
             sprite_counter += 1;
         }
 
+        // projectiles, 4x4 around their middle
+        for index in 0..frame.projectile_positions.len().min(MAX_PROJECTILES) {
+            let Vec2(x, y) = frame.projectile_positions[index] - Vec2(2., 2.);
+            xy_data[sprite_counter * 4 + 0] = (x + 0., y + 0.);
+            xy_data[sprite_counter * 4 + 1] = (x + 4., y + 0.);
+            xy_data[sprite_counter * 4 + 2] = (x + 4., y + 4.);
+            xy_data[sprite_counter * 4 + 3] = (x + 0., y + 4.);
+
+            let Vec2(u, v) = crate::update::misc::PROJECTILE_UV;
+            uv_data[sprite_counter * 4 + 0] = (u + 0. + 0.05, v + 0. + 0.05);
+            uv_data[sprite_counter * 4 + 1] = (u + 4. - 0.05, v + 0. + 0.05);
+            uv_data[sprite_counter * 4 + 2] = (u + 4. - 0.05, v + 4. - 0.05);
+            uv_data[sprite_counter * 4 + 3] = (u + 0. + 0.05, v + 4. - 0.05);
+
+            sprite_counter += 1;
+        }
+
+        // trees go over the tanks and shots, hiding them
+        frame.terrain.for_each(|rel_x, rel_y, &terrain| {
+            if terrain != Terrain::Trees {
+                return;
+            }
+            let x = frame.static_blocks_offset.0 + (rel_x * 16) as f32;
+            let y = frame.static_blocks_offset.1 + (rel_y * 16) as f32;
+            xy_data[sprite_counter * 4 + 0] = (x + 0., y + 0.);
+            xy_data[sprite_counter * 4 + 1] = (x + 16., y + 0.);
+            xy_data[sprite_counter * 4 + 2] = (x + 16., y + 16.);
+            xy_data[sprite_counter * 4 + 3] = (x + 0., y + 16.);
+
+            let Vec2(u, v) = crate::update::misc::terrain_to_uv(terrain).unwrap();
+            uv_data[sprite_counter * 4 + 0] = (u + 0. + 0.05, v + 0. + 0.05);
+            uv_data[sprite_counter * 4 + 1] = (u + 16. - 0.05, v + 0. + 0.05);
+            uv_data[sprite_counter * 4 + 2] = (u + 16. - 0.05, v + 16. - 0.05);
+            uv_data[sprite_counter * 4 + 3] = (u + 0. + 0.05, v + 16. - 0.05);
+
+            sprite_counter += 1;
+        });
+
+        // editor cursor
+        if let Some((Vec2(x, y), Vec2(u, v))) = frame.editor_cursor {
+            xy_data[sprite_counter * 4 + 0] = (x + 0., y + 0.);
+            xy_data[sprite_counter * 4 + 1] = (x + 16., y + 0.);
+            xy_data[sprite_counter * 4 + 2] = (x + 16., y + 16.);
+            xy_data[sprite_counter * 4 + 3] = (x + 0., y + 16.);
+
+            uv_data[sprite_counter * 4 + 0] = (u + 0. + 0.05, v + 0. + 0.05);
+            uv_data[sprite_counter * 4 + 1] = (u + 16. - 0.05, v + 0. + 0.05);
+            uv_data[sprite_counter * 4 + 2] = (u + 16. - 0.05, v + 16. - 0.05);
+            uv_data[sprite_counter * 4 + 3] = (u + 0. + 0.05, v + 16. - 0.05);
+
+            sprite_counter += 1;
+        }
+
+        // the hud, a half size tank for every enemy left to beat along the top of the view
+        for index in 0..(frame.remaining_tanks as usize).min(MAX_HUD_TANKS) {
+            let x = frame.camera.0 + frame.shake.0 + 2. + (index * 9) as f32;
+            let y = frame.camera.1 + frame.shake.1 + 2.;
+            xy_data[sprite_counter * 4 + 0] = (x + 0., y + 0.);
+            xy_data[sprite_counter * 4 + 1] = (x + 8., y + 0.);
+            xy_data[sprite_counter * 4 + 2] = (x + 8., y + 8.);
+            xy_data[sprite_counter * 4 + 3] = (x + 0., y + 8.);
+
+            let Vec2(u, v) = crate::update::misc::tank_to_uv(TankState::Idle);
+            uv_data[sprite_counter * 4 + 0] = (u + 0. + 0.05, v + 0. + 0.05);
+            uv_data[sprite_counter * 4 + 1] = (u + 16. - 0.05, v + 0. + 0.05);
+            uv_data[sprite_counter * 4 + 2] = (u + 16. - 0.05, v + 16. - 0.05);
+            uv_data[sprite_counter * 4 + 3] = (u + 0. + 0.05, v + 16. - 0.05);
+
+            sprite_counter += 1;
+        }
+
         // upload the buffer data to the gpu
         xy_buf.splice(0, &xy_data[0..sprite_counter * 4]).unwrap();
         uv_buf.splice(0, &uv_data[0..sprite_counter * 4]).unwrap();
 
-        // generate the view transform from the camera
+        // generate the view transform from the camera, knocked about by any wall shake
         let view_transform = camera(
-            (frame.camera.0 / 0.25).floor() * 0.25,
-            (frame.camera.1 / 0.25).floor() * 0.25,
+            ((frame.camera.0 + frame.shake.0) / 0.25).floor() * 0.25,
+            ((frame.camera.1 + frame.shake.1) / 0.25).floor() * 0.25,
             (frame.camera.2 / 0.25).floor() * 0.25,
             (frame.camera.3 / 0.25).floor() * 0.25,
         );
 
-        // clear buffer
+        // clear buffer, going red once the round is lost or green once it's won
         unsafe {
+            match frame.game_state {
+                GameState::GameOver => ezgl::gl::ClearColor(1., 0.6, 0.6, 1.),
+                GameState::Won => ezgl::gl::ClearColor(0.6, 1., 0.6, 1.),
+                _ => ezgl::gl::ClearColor(1., 1., 1., 1.),
+            }
             ezgl::gl::Clear(ezgl::gl::COLOR_BUFFER_BIT);
         }
 
@@ -149,6 +298,13 @@ pub fn render_thread(window: ContextWrapper<NotCurrent, Window>, render_r: Recei
 
         // swap
         window.swap_buffers().unwrap();
+
+        if shown_score != Some(frame.score) {
+            window
+                .window()
+                .set_title(&format!("Blockhop - score {}", frame.score));
+            shown_score = Some(frame.score);
+        }
     }
 }
 
@@ -5,20 +5,35 @@ use crate::update::misc::*;
 pub struct RenderState {
     // misc
     pub exit: bool,
+    pub game_state: GameState,
     pub time: u8,
-    pub remaining_tanks: u8,
+    pub remaining_tanks: u8, // enemies left to beat
+    pub score: u32,
     pub camera: Vec4<f32>,
 
     // static blocks
     pub static_blocks_offset: Vec2f,
     pub static_block_types: Array2D<Option<BlockType>>,
 
+    // the ground, over the same cells, with trees drawn over the tanks
+    pub terrain: Array2D<Terrain>,
+
     // sliding blocks
     pub sliding_block_positions: Box<[Vec2f]>,
     pub sliding_block_types: Box<[BlockType]>,
 
+    // projectiles, by their middle
+    pub projectile_positions: Box<[Vec2f]>,
+
     // tanks
     pub tank_positions: Box<[Vec2f]>,
     pub tank_directions: Box<[Direction]>,
     pub tank_states: Box<[TankState]>,
+    pub tank_shielded: Box<[bool]>,
+
+    // how far to knock the view off, while a wall is shaking
+    pub shake: Vec2f,
+
+    // editor, the position and uv of the brush under the mouse
+    pub editor_cursor: Option<(Vec2f, Vec2f)>,
 }
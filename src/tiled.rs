@@ -0,0 +1,655 @@
+// Imports maps made in Tiled (https://www.mapeditor.org), saved as either TMX or JSON.
+//
+// Tile layers become the static block map. Each tile says what block it is
+// through its tileset properties:
+//   block      solid, normal, oneway, bonus, diamond, egg, brick or base (the tile's class works too)
+//   direction  up, down, left or right, for one-way blocks
+//   terrain    floor, water, ice or trees, for the ground instead
+// Tiles without either property are left empty. Later layers draw over earlier ones.
+//
+// Object layers hold everything else, sorted by the object's class:
+//   tank   with properties controller (player, wander, chase or patrol),
+//          direction, and route (u/d/l/r moves) for patrols
+//   slide  with properties direction and block (a map character from level.rs)
+//   spawn  where wave tanks come in
+// Objects go in whichever cell their center is over.
+//
+// The map's own properties can set time, lives, hatch (seconds, space separated) and
+// waves, one per line (or split by ';'), each the seconds in then wander or chase tanks:
+//   10 wander wander; 30 chase wander chase
+//
+// Layer data has to be saved as CSV (or XML in TMX files), not base64.
+
+use crate::array2d::*;
+use crate::common::*;
+use crate::level::*;
+use crate::update::misc::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct TiledError(pub String);
+
+impl std::fmt::Display for TiledError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TiledError {}
+
+fn error<T>(message: impl Into<String>) -> Result<T, TiledError> {
+    Err(TiledError(message.into()))
+}
+
+// the top 4 bits of a gid are flip/rotation flags
+const GID_MASK: u32 = 0x0FFF_FFFF;
+
+type Properties = HashMap<String, String>;
+
+struct Tileset {
+    first_gid: u32,
+    tiles: HashMap<u32, Properties>,
+}
+
+struct Object {
+    class: String,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    tile: bool,
+    properties: Properties,
+}
+
+enum Layer {
+    Tiles(Vec<u32>),
+    Objects(Vec<Object>),
+}
+
+struct Map {
+    width: usize,
+    height: usize,
+    tile_width: f32,
+    tile_height: f32,
+    properties: Properties,
+    tilesets: Vec<Tileset>,
+    layers: Vec<Layer>,
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<Level, TiledError> {
+    let path = path.as_ref();
+    let src = read(path)?;
+    let map = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("tmx") => tmx::map(&src, path)?,
+        Some("json") | Some("tmj") => json::map(&src, path)?,
+        _ => return error(format!("{} is not a .tmx or .json map", path.display())),
+    };
+    to_level(&map)
+}
+
+fn read(path: &Path) -> Result<String, TiledError> {
+    std::fs::read_to_string(path).map_err(|err| TiledError(format!("{}: {}", path.display(), err)))
+}
+
+// external tilesets are relative to the map that uses them
+fn load_tileset(first_gid: u32, source: &str, map_path: &Path) -> Result<Tileset, TiledError> {
+    let path = map_path.parent().unwrap_or(Path::new("")).join(source);
+    let src = read(&path)?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("tsx") => tmx::tileset(first_gid, &src),
+        Some("json") | Some("tsj") => json::tileset(first_gid, &src),
+        _ => error(format!("{} is not a .tsx or .json tileset", path.display())),
+    }
+}
+
+fn to_level(map: &Map) -> Result<Level, TiledError> {
+    let property = |properties: &Properties, name: &str| properties.get(name).cloned();
+    let number = |properties: &Properties, name: &str, default: u64| match properties.get(name) {
+        Some(value) => value.parse().or_else(|_| {
+            error(format!(
+                "property {} should be a number, not \"{}\"",
+                name, value
+            ))
+        }),
+        None => Ok(default),
+    };
+    let direction = |properties: &Properties, default: Direction| match properties.get("direction")
+    {
+        Some(value) => parse_direction(value)
+            .map_or_else(|| error(format!("unknown direction \"{}\"", value)), Ok),
+        None => Ok(default),
+    };
+
+    // what each tile in every tileset turns into
+    let mut blocks = HashMap::new();
+    let mut ground = HashMap::new();
+    for tileset in &map.tilesets {
+        for (&id, properties) in &tileset.tiles {
+            let terrain = match property(properties, "terrain").as_ref().map(|s| s.as_str()) {
+                None => None,
+                Some("floor") => Some(Terrain::Floor),
+                Some("water") => Some(Terrain::Water),
+                Some("ice") => Some(Terrain::Ice),
+                Some("trees") => Some(Terrain::Trees),
+                Some(other) => {
+                    return error(format!("tile {} has unknown terrain \"{}\"", id, other))
+                }
+            };
+            if let Some(terrain) = terrain {
+                ground.insert(tileset.first_gid + id, terrain);
+            }
+
+            let block_type = match property(properties, "block").as_ref().map(|s| s.as_str()) {
+                None => continue,
+                Some("solid") => BlockType::Solid,
+                Some("normal") => BlockType::Normal,
+                Some("oneway") => BlockType::OneWay(direction(properties, Direction::Up)?),
+                Some("bonus") => BlockType::TimeBonus,
+                Some("diamond") => BlockType::Diamond,
+                Some("egg") => BlockType::Egg,
+                Some("brick") => BlockType::Brick(BRICK_WHOLE),
+                Some("base") => BlockType::Base,
+                Some(other) => {
+                    return error(format!("tile {} has unknown block \"{}\"", id, other))
+                }
+            };
+            blocks.insert(tileset.first_gid + id, block_type);
+        }
+    }
+
+    // tile layers
+    let mut static_block_types = Array2D::from_closure(map.width, map.height, |_, _| None);
+    let mut terrain = Array2D::from_closure(map.width, map.height, |_, _| Terrain::Floor);
+    for layer in &map.layers {
+        let data = match layer {
+            Layer::Tiles(data) => data,
+            _ => continue,
+        };
+        if data.len() != map.width * map.height {
+            return error("a tile layer is a different size to the map");
+        }
+        for (index, &gid) in data.iter().enumerate() {
+            if let Some(&block_type) = blocks.get(&(gid & GID_MASK)) {
+                static_block_types[index] = Some(block_type);
+            }
+            if let Some(&ground) = ground.get(&(gid & GID_MASK)) {
+                terrain[index] = ground;
+            }
+        }
+    }
+
+    if let Some((x, y)) = border_gap(&static_block_types) {
+        return error(format!(
+            "the edge of the map has to be solid wall, but cell {}, {} isn't",
+            x, y
+        ));
+    }
+
+    // object layers
+    let mut tanks = vec![];
+    let mut sliding_blocks = vec![];
+    let mut spawns = vec![];
+    for layer in &map.layers {
+        let objects = match layer {
+            Layer::Objects(objects) => objects,
+            _ => continue,
+        };
+        for object in objects {
+            // tile objects hang up from their bottom left corner
+            let top = match object.tile {
+                true => object.y - object.height,
+                false => object.y,
+            };
+            let cx = (object.x + object.width / 2.) / map.tile_width;
+            let cy = (top + object.height / 2.) / map.tile_height;
+            if cx < 0. || cy < 0. || cx as usize >= map.width || cy as usize >= map.height {
+                return error(format!("a {} object is outside of the map", object.class));
+            }
+            let (x, y) = (cx as usize, cy as usize);
+            if static_block_types[(x, y)].is_some() {
+                return error(format!(
+                    "a {} object at cell {}, {} is on top of a block",
+                    object.class, x, y
+                ));
+            }
+
+            let properties = &object.properties;
+            match object.class.as_str() {
+                "tank" => {
+                    let controller = match property(properties, "controller")
+                        .as_ref()
+                        .map(|s| s.as_str())
+                    {
+                        Some("player") => Controller::Player,
+                        Some("wander") | None => Controller::Wander,
+                        Some("chase") => Controller::Chase,
+                        Some("patrol") => {
+                            let route = property(properties, "route").unwrap_or_default();
+                            let route = route
+                                .chars()
+                                .map(|c| parse_direction(&c.to_string()))
+                                .collect::<Option<Vec<_>>>();
+                            match route {
+                                Some(route) => Controller::Patrol { route, step: 0 },
+                                None => return error("patrol routes are made of u, d, l and r"),
+                            }
+                        }
+                        Some(other) => return error(format!("unknown controller \"{}\"", other)),
+                    };
+                    tanks.push(TankSpawn {
+                        x,
+                        y,
+                        direction: direction(properties, Direction::Up)?,
+                        controller,
+                    });
+                }
+
+                "slide" => {
+                    let block = property(properties, "block").unwrap_or_else(|| "o".to_string());
+                    let mut chars = block.chars();
+                    let block_type = match (chars.next().and_then(char_to_block), chars.next()) {
+                        (Some(Some(block_type)), None) => block_type,
+                        _ => return error(format!("\"{}\" is not a block character", block)),
+                    };
+                    sliding_blocks.push(SlidingBlockSpawn {
+                        x,
+                        y,
+                        direction: direction(properties, Direction::Right)?,
+                        block_type,
+                    });
+                }
+
+                "spawn" => spawns.push((x, y)),
+
+                // anything else is left for the designers
+                _ => {}
+            }
+        }
+    }
+
+    let players = tanks
+        .iter()
+        .filter(|tank| match tank.controller {
+            Controller::Player => true,
+            _ => false,
+        })
+        .count();
+    if players != 1 {
+        return error(format!(
+            "the map needs exactly one player tank, not {}",
+            players
+        ));
+    }
+
+    let lives = match number(&map.properties, "lives", DEFAULT_LIVES as u64)? {
+        n @ 1..=255 => n as u8,
+        _ => return error("lives must be between 1 and 255"),
+    };
+
+    // times end up in microseconds, so they have to fit once multiplied up
+    let seconds = |time: u64| match time {
+        time if time <= MAX_TIME => Ok(time),
+        _ => error(format!("times can't be more than {} seconds", MAX_TIME)),
+    };
+
+    let mut hatch = vec![];
+    for time in property(&map.properties, "hatch")
+        .unwrap_or_default()
+        .split_whitespace()
+    {
+        match time.parse() {
+            Ok(time) => hatch.push(seconds(time)?),
+            Err(_) => return error(format!("hatch times should be numbers, not \"{}\"", time)),
+        }
+    }
+    hatch.sort();
+
+    let mut waves = vec![];
+    for wave in property(&map.properties, "waves")
+        .unwrap_or_default()
+        .split(|c| c == ';' || c == '\n')
+    {
+        let mut words = wave.split_whitespace();
+        let time = match words.next() {
+            Some(time) => time,
+            None => continue,
+        };
+        let time = match time.parse() {
+            Ok(time) => seconds(time)?,
+            Err(_) => return error(format!("wave times should be numbers, not \"{}\"", time)),
+        };
+        let tanks = words
+            .map(|kind| match kind {
+                "wander" => Ok(Controller::Wander),
+                "chase" => Ok(Controller::Chase),
+                _ => error(format!(
+                    "waves send wander or chase tanks, not \"{}\"",
+                    kind
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if tanks.is_empty() {
+            return error(format!("the wave at {} seconds sends no tanks", time));
+        }
+        waves.push(Wave { time, tanks });
+    }
+    if !waves.is_empty() && spawns.is_empty() {
+        return error("waves need a spawn object to come in at");
+    }
+    waves.sort_by_key(|wave| wave.time);
+
+    Ok(Level {
+        time: seconds(number(&map.properties, "time", DEFAULT_TIME)?)?,
+        lives,
+        hatch,
+        spawns,
+        waves,
+        static_block_types,
+        terrain,
+        tanks,
+        sliding_blocks,
+    })
+}
+
+mod tmx {
+    use super::*;
+    use roxmltree::{Document, Node};
+
+    fn attribute<T: std::str::FromStr>(node: Node, name: &str) -> Result<T, TiledError> {
+        let value = match node.attribute(name) {
+            Some(value) => value,
+            None => return error(format!("<{}> is missing {}", node.tag_name().name(), name)),
+        };
+        value.parse().or_else(|_| {
+            error(format!(
+                "<{}> has a bad {} \"{}\"",
+                node.tag_name().name(),
+                name,
+                value
+            ))
+        })
+    }
+
+    fn children<'a, 'input>(
+        node: Node<'a, 'input>,
+        name: &'static str,
+    ) -> impl Iterator<Item = Node<'a, 'input>> {
+        node.children()
+            .filter(move |child| child.has_tag_name(name))
+    }
+
+    fn properties(node: Node) -> Properties {
+        children(node, "properties")
+            .flat_map(|properties| children(properties, "property"))
+            .filter_map(|property| {
+                let name = property.attribute("name")?;
+                // multiline strings live in the text instead
+                let value = property.attribute("value").or_else(|| property.text())?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect()
+    }
+
+    fn tiles(node: Node) -> Result<HashMap<u32, Properties>, TiledError> {
+        children(node, "tile")
+            .map(|tile| {
+                let mut properties = properties(tile);
+                if let Some(class) = tile.attribute("class").or_else(|| tile.attribute("type")) {
+                    properties
+                        .entry("block".to_string())
+                        .or_insert_with(|| class.to_string());
+                }
+                Ok((attribute(tile, "id")?, properties))
+            })
+            .collect()
+    }
+
+    pub(super) fn tileset(first_gid: u32, src: &str) -> Result<Tileset, TiledError> {
+        let doc = Document::parse(src).map_err(|err| TiledError(err.to_string()))?;
+        Ok(Tileset {
+            first_gid,
+            tiles: tiles(doc.root_element())?,
+        })
+    }
+
+    fn data(node: Node) -> Result<Vec<u32>, TiledError> {
+        let data = match children(node, "data").next() {
+            Some(data) => data,
+            None => return error("a tile layer has no data"),
+        };
+        match data.attribute("encoding") {
+            None => children(data, "tile")
+                .map(|tile| match tile.attribute("gid") {
+                    Some(_) => attribute(tile, "gid"),
+                    None => Ok(0),
+                })
+                .collect(),
+            Some("csv") => data
+                .text()
+                .unwrap_or("")
+                .split(',')
+                .map(|gid| {
+                    gid.trim()
+                        .parse()
+                        .or_else(|_| error(format!("bad tile \"{}\"", gid.trim())))
+                })
+                .collect(),
+            Some(encoding) => error(format!(
+                "{} layer data isn't supported, save as CSV",
+                encoding
+            )),
+        }
+    }
+
+    pub(super) fn map(src: &str, path: &Path) -> Result<Map, TiledError> {
+        let doc = Document::parse(src).map_err(|err| TiledError(err.to_string()))?;
+        let root = doc.root_element();
+
+        let tilesets = children(root, "tileset")
+            .map(|tileset| {
+                let first_gid = attribute(tileset, "firstgid")?;
+                match tileset.attribute("source") {
+                    Some(source) => load_tileset(first_gid, source, path),
+                    None => Ok(Tileset {
+                        first_gid,
+                        tiles: tiles(tileset)?,
+                    }),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        // groups are flattened into the layers they contain
+        fn layers(node: Node, out: &mut Vec<Layer>) -> Result<(), TiledError> {
+            for child in node.children() {
+                match child.tag_name().name() {
+                    "layer" => out.push(Layer::Tiles(data(child)?)),
+                    "objectgroup" => out.push(Layer::Objects(
+                        children(child, "object")
+                            .map(|object| {
+                                let class = object
+                                    .attribute("class")
+                                    .or_else(|| object.attribute("type"));
+                                Ok(Object {
+                                    class: class.unwrap_or("").to_string(),
+                                    x: attribute(object, "x")?,
+                                    y: attribute(object, "y")?,
+                                    width: object
+                                        .attribute("width")
+                                        .map_or(Ok(0.), |_| attribute(object, "width"))?,
+                                    height: object
+                                        .attribute("height")
+                                        .map_or(Ok(0.), |_| attribute(object, "height"))?,
+                                    tile: object.attribute("gid").is_some(),
+                                    properties: properties(object),
+                                })
+                            })
+                            .collect::<Result<_, TiledError>>()?,
+                    )),
+                    "group" => layers(child, out)?,
+                    _ => {}
+                }
+            }
+            Ok(())
+        }
+        let mut all_layers = vec![];
+        layers(root, &mut all_layers)?;
+
+        Ok(Map {
+            width: attribute(root, "width")?,
+            height: attribute(root, "height")?,
+            tile_width: attribute(root, "tilewidth")?,
+            tile_height: attribute(root, "tileheight")?,
+            properties: properties(root),
+            tilesets,
+            layers: all_layers,
+        })
+    }
+}
+
+mod json {
+    use super::*;
+    use serde_json::Value;
+
+    fn field<'a>(value: &'a Value, name: &str) -> Result<&'a Value, TiledError> {
+        match value.get(name) {
+            Some(field) => Ok(field),
+            None => error(format!("missing \"{}\"", name)),
+        }
+    }
+
+    fn number(value: &Value, name: &str) -> Result<f64, TiledError> {
+        match field(value, name)?.as_f64() {
+            Some(number) => Ok(number),
+            None => error(format!("\"{}\" should be a number", name)),
+        }
+    }
+
+    fn array<'a>(value: &'a Value, name: &str) -> Result<&'a [Value], TiledError> {
+        match value.get(name) {
+            Some(Value::Array(array)) => Ok(array),
+            Some(_) => error(format!("\"{}\" should be a list", name)),
+            None => Ok(&[]),
+        }
+    }
+
+    fn string<'a>(value: &'a Value, name: &str) -> Option<&'a str> {
+        value.get(name).and_then(Value::as_str)
+    }
+
+    fn properties(value: &Value) -> Result<Properties, TiledError> {
+        array(value, "properties")?
+            .iter()
+            .map(|property| {
+                let name = match string(property, "name") {
+                    Some(name) => name.to_string(),
+                    None => return error("a property has no name"),
+                };
+                let value = match field(property, "value")? {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                Ok((name, value))
+            })
+            .collect()
+    }
+
+    fn tiles(value: &Value) -> Result<HashMap<u32, Properties>, TiledError> {
+        array(value, "tiles")?
+            .iter()
+            .map(|tile| {
+                let mut properties = properties(tile)?;
+                if let Some(class) = string(tile, "class").or_else(|| string(tile, "type")) {
+                    properties
+                        .entry("block".to_string())
+                        .or_insert_with(|| class.to_string());
+                }
+                Ok((number(tile, "id")? as u32, properties))
+            })
+            .collect()
+    }
+
+    fn parse(src: &str) -> Result<Value, TiledError> {
+        serde_json::from_str(src).map_err(|err| TiledError(err.to_string()))
+    }
+
+    pub(super) fn tileset(first_gid: u32, src: &str) -> Result<Tileset, TiledError> {
+        Ok(Tileset {
+            first_gid,
+            tiles: tiles(&parse(src)?)?,
+        })
+    }
+
+    fn layers(value: &Value, out: &mut Vec<Layer>) -> Result<(), TiledError> {
+        for layer in array(value, "layers")? {
+            match string(layer, "type") {
+                Some("tilelayer") => {
+                    let data = match layer.get("data") {
+                        Some(Value::Array(data)) => data
+                            .iter()
+                            .map(|gid| gid.as_u64().map(|gid| gid as u32))
+                            .collect::<Option<_>>(),
+                        _ => None,
+                    };
+                    match data {
+                        Some(data) => out.push(Layer::Tiles(data)),
+                        None => return error("layer data isn't supported, save as CSV"),
+                    }
+                }
+                Some("objectgroup") => out.push(Layer::Objects(
+                    array(layer, "objects")?
+                        .iter()
+                        .map(|object| {
+                            let class = string(object, "class").or_else(|| string(object, "type"));
+                            Ok(Object {
+                                class: class.unwrap_or("").to_string(),
+                                x: number(object, "x")? as f32,
+                                y: number(object, "y")? as f32,
+                                width: object.get("width").and_then(Value::as_f64).unwrap_or(0.)
+                                    as f32,
+                                height: object.get("height").and_then(Value::as_f64).unwrap_or(0.)
+                                    as f32,
+                                tile: object.get("gid").is_some(),
+                                properties: properties(object)?,
+                            })
+                        })
+                        .collect::<Result<_, TiledError>>()?,
+                )),
+                Some("group") => layers(layer, out)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn map(src: &str, path: &Path) -> Result<Map, TiledError> {
+        let root = parse(src)?;
+
+        let tilesets = array(&root, "tilesets")?
+            .iter()
+            .map(|tileset| {
+                let first_gid = number(tileset, "firstgid")? as u32;
+                match string(tileset, "source") {
+                    Some(source) => load_tileset(first_gid, source, path),
+                    None => Ok(Tileset {
+                        first_gid,
+                        tiles: tiles(tileset)?,
+                    }),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut all_layers = vec![];
+        layers(&root, &mut all_layers)?;
+
+        Ok(Map {
+            width: number(&root, "width")? as usize,
+            height: number(&root, "height")? as usize,
+            tile_width: number(&root, "tilewidth")? as f32,
+            tile_height: number(&root, "tileheight")? as f32,
+            properties: properties(&root)?,
+            tilesets,
+            layers: all_layers,
+        })
+    }
+}
@@ -0,0 +1,68 @@
+use super::misc::*;
+use crate::common::*;
+
+// everything the editor can paint, in the order B cycles through them
+pub const BLOCK_PALETTE: [BlockType; 11] = [
+    BlockType::Solid,
+    BlockType::Normal,
+    BlockType::OneWay(Direction::Up),
+    BlockType::OneWay(Direction::Down),
+    BlockType::OneWay(Direction::Left),
+    BlockType::OneWay(Direction::Right),
+    BlockType::TimeBonus,
+    BlockType::Diamond,
+    BlockType::Egg,
+    BlockType::Brick(BRICK_WHOLE),
+    BlockType::Base,
+];
+
+// and the tank spawns T cycles through
+pub fn tank_palette(index: usize) -> Controller {
+    match index % 3 {
+        0 => Controller::Player,
+        1 => Controller::Wander,
+        _ => Controller::Chase,
+    }
+}
+
+pub const TANK_PALETTE_LEN: usize = 3;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Brush {
+    Block(usize),
+    Tank(usize),
+}
+
+pub struct Editor {
+    pub brush: Brush,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self {
+            brush: Brush::Block(0),
+        }
+    }
+
+    pub fn next_block(&mut self) {
+        self.brush = match self.brush {
+            Brush::Block(index) => Brush::Block((index + 1) % BLOCK_PALETTE.len()),
+            Brush::Tank(_) => Brush::Block(0),
+        };
+    }
+
+    pub fn next_tank(&mut self) {
+        self.brush = match self.brush {
+            Brush::Tank(index) => Brush::Tank((index + 1) % TANK_PALETTE_LEN),
+            Brush::Block(_) => Brush::Tank(0),
+        };
+    }
+}
+
+// the inverse of the renderer's camera transform, from window pixels to world pixels
+pub fn screen_to_world(cursor: Vec2f, window_size: Vec2f, camera: Vec4f) -> Vec2f {
+    let Vec2(cx, cy) = cursor;
+    let Vec2(ww, wh) = window_size;
+    let Vec4(x, y, w, h) = camera;
+    Vec2(x + cx / ww * w, y + cy / wh * h)
+}
@@ -1,4 +1,5 @@
 #[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Key {
     A,
     B,
@@ -32,13 +33,139 @@ pub enum Key {
     Right,
     Lmb,
     Rmb,
+    F5,
+    F9,
 }
 
 #[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Event {
     KeyDown(Key),
     KeyUp(Key),
     MouseMove(f32, f32),
+    Resize(f32, f32),
     Exit,
     Invalid,
 }
+
+impl Key {
+    pub fn name(self) -> &'static str {
+        match self {
+            Key::A => "a",
+            Key::B => "b",
+            Key::C => "c",
+            Key::D => "d",
+            Key::E => "e",
+            Key::F => "f",
+            Key::G => "g",
+            Key::H => "h",
+            Key::I => "i",
+            Key::J => "j",
+            Key::K => "k",
+            Key::L => "l",
+            Key::M => "m",
+            Key::N => "n",
+            Key::O => "o",
+            Key::P => "p",
+            Key::Q => "q",
+            Key::R => "r",
+            Key::S => "s",
+            Key::T => "t",
+            Key::U => "u",
+            Key::V => "v",
+            Key::W => "w",
+            Key::X => "x",
+            Key::Y => "y",
+            Key::Z => "z",
+            Key::Up => "up",
+            Key::Down => "down",
+            Key::Left => "left",
+            Key::Right => "right",
+            Key::Lmb => "lmb",
+            Key::Rmb => "rmb",
+            Key::F5 => "f5",
+            Key::F9 => "f9",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        ALL_KEYS.iter().copied().find(|key| key.name() == name)
+    }
+
+    // a stable number for each key, for binary files
+    pub fn index(self) -> u8 {
+        ALL_KEYS.iter().position(|&key| key == self).unwrap() as u8
+    }
+
+    pub fn from_index(index: u8) -> Option<Self> {
+        ALL_KEYS.get(index as usize).copied()
+    }
+}
+
+// new keys go on the end, replays store keys by their place in this list
+const ALL_KEYS: [Key; 34] = [
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+    Key::Up,
+    Key::Down,
+    Key::Left,
+    Key::Right,
+    Key::Lmb,
+    Key::Rmb,
+    Key::F5,
+    Key::F9,
+];
+
+// events as text, for scripts:
+//   press z, release left, mouse 120 48, resize 800 720, exit
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Event::KeyDown(key) => write!(f, "press {}", key.name()),
+            Event::KeyUp(key) => write!(f, "release {}", key.name()),
+            Event::MouseMove(x, y) => write!(f, "mouse {} {}", x, y),
+            Event::Resize(w, h) => write!(f, "resize {} {}", w, h),
+            Event::Exit => write!(f, "exit"),
+            Event::Invalid => write!(f, "invalid"),
+        }
+    }
+}
+
+impl Event {
+    pub fn parse(s: &str) -> Option<Self> {
+        let words: Vec<_> = s.split_whitespace().collect();
+        let number = |word: &str| word.parse::<f32>().ok();
+        match words.as_slice() {
+            ["press", key] => Some(Event::KeyDown(Key::from_name(key)?)),
+            ["release", key] => Some(Event::KeyUp(Key::from_name(key)?)),
+            ["mouse", x, y] => Some(Event::MouseMove(number(x)?, number(y)?)),
+            ["resize", w, h] => Some(Event::Resize(number(w)?, number(h)?)),
+            ["exit"] => Some(Event::Exit),
+            _ => None,
+        }
+    }
+}
@@ -1,47 +1,64 @@
 use super::misc::*;
+use super::timer::*;
+use crate::array2d::*;
 use crate::common::*;
 use std::ops::{Index, IndexMut};
 
-pub fn tank_delay(
+pub const SLIDING_BLOCK_SPEED: f32 = 1.; // pixels per tick
+pub const TANK_MOVE_TIME: u64 = 400_000; // microseconds
+pub const TANK_PUSH_DELAY: u64 = 1_000_000; // microseconds
+pub const TANK_EXPLOSION_TIME: u64 = 500_000; // microseconds
+pub const TIME_BONUS: u64 = 10_000_000; // microseconds
+pub const TANK_SPAWN_SHIELD: u64 = 2_000_000; // microseconds
+pub const TANK_STUN_TIME: u64 = 3_000_000; // microseconds
+pub const WALL_SHAKE_TIME: u64 = 300_000; // microseconds
+pub const DIAMOND_LINE_SCORE: u32 = 5_000; // points
+pub const EGG_SCORE: u32 = 500; // points
+pub const TANK_FIRE_DELAY: u64 = 500_000; // microseconds
+pub const PROJECTILE_SPEED: f32 = 3.; // pixels per tick
+pub const ICE_SLIDE_STEPS: u8 = 2; // steps, one cell
+
+pub fn tank_transitions(
     tanks: usize,
     us_timestamp: u64,
+    tank_ids: &(impl Index<usize, Output = u32> + ?Sized),
     tank_states: &mut (impl IndexMut<usize, Output = TankState> + ?Sized),
+    tank_schedule: &mut Scheduler<(u32, TankState)>,
 ) {
-    // calculate ms timestamp as u16
-    let ms_timestamp = (us_timestamp / 1000) as u16;
-
-    for index in 0..tanks {
-        // skip non-delayed tanks
-        let (timestamp, duration) = match tank_states[index] {
-            TankState::Delayed {
-                timestamp,
-                duration,
-            } => (timestamp, duration),
-            _ => continue,
-        };
-
-        // idle
-        let diff = (ms_timestamp).wrapping_sub(timestamp);
-        if diff > duration {
-            tank_states[index] = TankState::Idle;
+    for (id, state) in tank_schedule.due(us_timestamp) {
+        // the tank may have been removed since
+        if let Some(index) = (0..tanks).find(|&index| tank_ids[index] == id) {
+            tank_states[index] = state;
         }
     }
 }
 
-pub fn tank_ai(
+pub fn tank_control(
     tanks: usize,
+    player_input: PlayerInput,
+    rng: &mut Rng,
+    tank_positions: &(impl Index<usize, Output = Vec2f> + ?Sized),
+    tank_directions: &(impl Index<usize, Output = Direction> + ?Sized),
     tank_states: &(impl Index<usize, Output = TankState> + ?Sized),
-    zkey_down: bool,
-    upkey_down: bool,
-    downkey_down: bool,
-    leftkey_down: bool,
-    rightkey_down: bool,
+    tank_controllers: &mut (impl IndexMut<usize, Output = Controller> + ?Sized),
+    static_block_types: &Array2D<Option<BlockType>>,
+    terrain: &Array2D<Terrain>,
 ) -> (
     impl Iterator<Item = usize>,
     impl Iterator<Item = (usize, Direction)>,
+    impl Iterator<Item = usize>,
 ) {
     let mut push = vec![];
     let mut mov = vec![];
+    let mut fire = vec![];
+
+    // chasers all go after the first player tank
+    let player_pos = (0..tanks)
+        .find(|&index| match tank_controllers[index] {
+            Controller::Player => true,
+            _ => false,
+        })
+        .map(|index| tank_positions[index]);
 
     for index in 0..tanks {
         // skip non-idle tanks
@@ -50,48 +67,177 @@ pub fn tank_ai(
             _ => continue,
         };
 
-        //
-        if zkey_down {
-            push.push(index);
-            continue;
-        }
+        let pos = tank_positions[index];
+        let dir = tank_directions[index];
 
-        // move
-        let mdir = if rightkey_down {
-            Some(Direction::Right)
-        } else if leftkey_down {
-            Some(Direction::Left)
-        } else if upkey_down {
-            Some(Direction::Up)
-        } else if downkey_down {
-            Some(Direction::Down)
-        } else {
-            None
-        };
+        match &mut tank_controllers[index] {
+            Controller::Player => {
+                if player_input.push {
+                    push.push(index);
+                    continue;
+                }
+                if player_input.fire {
+                    fire.push(index);
+                    continue;
+                }
 
-        if let Some(dir) = mdir {
-            mov.push((index, dir));
-            continue;
+                // move
+                let mdir = if player_input.right {
+                    Some(Direction::Right)
+                } else if player_input.left {
+                    Some(Direction::Left)
+                } else if player_input.up {
+                    Some(Direction::Up)
+                } else if player_input.down {
+                    Some(Direction::Down)
+                } else {
+                    None
+                };
+
+                if let Some(dir) = mdir {
+                    mov.push((index, dir));
+                }
+            }
+
+            Controller::Wander => {
+                // kick whatever is ahead every so often
+                if rng.range(16) == 0 {
+                    push.push(index);
+                    continue;
+                }
+
+                // and take the odd shot
+                if rng.range(32) == 0 {
+                    fire.push(index);
+                    continue;
+                }
+
+                // mostly keep rolling forward, turning at random or when blocked
+                if tank_can_move(pos, dir, static_block_types, terrain) && rng.range(8) != 0 {
+                    mov.push((index, dir));
+                } else {
+                    mov.push((index, rng.direction()));
+                }
+            }
+
+            Controller::Patrol { route, step } => {
+                if route.is_empty() {
+                    continue;
+                }
+
+                // only advance along the route once the move can be made
+                let next = route[*step % route.len()];
+                if tank_can_move(pos, next, static_block_types, terrain) {
+                    *step = (*step + 1) % route.len();
+                }
+                mov.push((index, next));
+            }
+
+            Controller::Chase => {
+                let player_pos = match player_pos {
+                    Some(player_pos) => player_pos,
+                    None => continue,
+                };
+                let Vec2(dx, dy) = player_pos - pos;
+
+                // lined up and facing the player, so kick a block at them,
+                // or shoot if there's nothing but bricks in the way
+                let facing = match dir {
+                    Direction::Up => dx.abs() < 8. && dy < 0.,
+                    Direction::Down => dx.abs() < 8. && dy > 0.,
+                    Direction::Left => dy.abs() < 8. && dx < 0.,
+                    Direction::Right => dy.abs() < 8. && dx > 0.,
+                };
+                if facing && !tank_can_move(pos, dir, static_block_types, terrain) {
+                    push.push(index);
+                    continue;
+                }
+                if facing && clear_shot(static_block_types, pos, dir, player_pos) {
+                    fire.push(index);
+                    continue;
+                }
+
+                // follow the shortest route around the walls when there is one
+                let path = super::path::find_path(static_block_types, terrain, pos, player_pos);
+                if let Some(&next) = path.as_ref().and_then(|path| path.first()) {
+                    mov.push((index, next));
+                    continue;
+                }
+
+                // otherwise close the larger gap first, falling back to the other axis when blocked
+                let horizontal = match dx < 0. {
+                    true => Direction::Left,
+                    false => Direction::Right,
+                };
+                let vertical = match dy < 0. {
+                    true => Direction::Up,
+                    false => Direction::Down,
+                };
+                let (first, second) = match dx.abs() > dy.abs() {
+                    true => (horizontal, vertical),
+                    false => (vertical, horizontal),
+                };
+                if tank_can_move(pos, first, static_block_types, terrain) {
+                    mov.push((index, first));
+                } else {
+                    mov.push((index, second));
+                }
+            }
         }
     }
 
-    (push.into_iter(), mov.into_iter())
+    (push.into_iter(), mov.into_iter(), fire.into_iter())
+}
+
+// whether a shot from one tank would make it to the row or column of another it's facing,
+// breaking through any bricks on the way
+fn clear_shot(
+    static_block_types: &Array2D<Option<BlockType>>,
+    pos: Vec2f,
+    dir: Direction,
+    target: Vec2f,
+) -> bool {
+    let cell = |v: f32| ((v + 8.) / 16.) as i32;
+    let (mut x, mut y) = (cell(pos.0), cell(pos.1));
+    let (tx, ty) = (cell(target.0), cell(target.1));
+    let Vec2(dx, dy) = dir.vec2i();
+    loop {
+        x += dx;
+        y += dy;
+        if (dx != 0 && x == tx) || (dy != 0 && y == ty) {
+            return true;
+        }
+        match static_block_types.coord_get(x as usize, y as usize) {
+            Some(None) | Some(Some(BlockType::TimeBonus)) | Some(Some(BlockType::Brick(_))) => {}
+            _ => return false,
+        }
+    }
 }
 
+// returns the tanks that shoved the outer wall instead, and which side of the map it was,
+// along with the tanks that smashed an egg
 pub fn tank_push_command(
     push: impl Iterator<Item = usize>,
     us_timestamp: u64,
-    static_block_types: &mut (impl IndexMut<(usize, usize), Output = Option<BlockType>> + ?Sized),
+    static_block_types: &mut Array2D<Option<BlockType>>,
+    terrain: &Array2D<Terrain>,
+    tank_ids: &(impl Index<usize, Output = u32> + ?Sized),
     tank_positions: &(impl Index<usize, Output = Vec2f> + ?Sized),
     tank_directions: &(impl Index<usize, Output = Direction> + ?Sized),
     tank_states: &mut (impl IndexMut<usize, Output = TankState> + ?Sized),
+    tank_schedule: &mut Scheduler<(u32, TankState)>,
     id_counter: &mut u32,
     sliding_block_ids: &mut Vec<u32>,
     sliding_block_positions: &mut Vec<Vec2f>,
     sliding_block_directions: &mut Vec<Direction>,
     sliding_block_types: &mut Vec<BlockType>,
-) {
-    let ms_timestamp = (us_timestamp / 1000) as u16;
+) -> (Vec<(usize, Direction)>, Vec<usize>) {
+    let mut shakes = vec![];
+    let mut smashed = vec![];
+    let (width, height) = (
+        static_block_types.get_width(),
+        static_block_types.get_height(),
+    );
 
     for index in push {
         // position and unit direction
@@ -113,6 +259,21 @@ pub fn tank_push_command(
             static_block_types[b2_pos]
         };
 
+        // pushing a wall on the edge of the map shakes it
+        let border = |(x, y)| x == 0 || y == 0 || x == width - 1 || y == height - 1;
+        let shake = [(b1, b1_pos), (b2, b2_pos)]
+            .iter()
+            .any(|&(b, pos)| b == Some(BlockType::Solid) && border(pos));
+        if shake {
+            shakes.push((index, tank_directions[index]));
+            tank_states[index] = TankState::Delayed;
+            tank_schedule.schedule(
+                us_timestamp + TANK_PUSH_DELAY,
+                (tank_ids[index], TankState::Idle),
+            );
+            continue;
+        }
+
         //
         let (b, (x, y)) = match (b1, b2) {
             // if both blocks exist or don't exist
@@ -126,6 +287,51 @@ pub fn tank_push_command(
             (Some(blk), None) => (blk, b1_pos),
         };
 
+        match b {
+            // one-way blocks only move along their own direction
+            BlockType::OneWay(dir) if dir != tank_directions[index] => continue,
+            // pickups, bricks and the base stay put
+            BlockType::TimeBonus | BlockType::Brick(_) | BlockType::Base => continue,
+            _ => {}
+        }
+
+        // an egg with nowhere to go is smashed against whatever is behind it
+        let Vec2(dx, dy) = tank_directions[index].vec2i();
+        let behind = ((x as i32 + dx) as usize, (y as i32 + dy) as usize);
+        let blocked = match static_block_types.coord_get(behind.0, behind.1) {
+            Some(None) => false,
+            _ => true,
+        };
+        if b == BlockType::Egg && blocked {
+            static_block_types[(x, y)] = None;
+            smashed.push(index);
+            tank_states[index] = TankState::Delayed;
+            tank_schedule.schedule(
+                us_timestamp + TANK_PUSH_DELAY,
+                (tank_ids[index], TankState::Idle),
+            );
+            continue;
+        }
+
+        // a block with nowhere to go stays put, only chipping a brick it's shoved against.
+        // lifting it would settle it straight back, and pay out again for any line it's in
+        let stuck = blocked
+            || match b {
+                BlockType::Diamond | BlockType::Egg => {
+                    terrain.coord_get(behind.0, behind.1) == Some(&Terrain::Water)
+                }
+                _ => false,
+            };
+        if stuck {
+            brick_chip(static_block_types, behind, tank_directions[index]);
+            tank_states[index] = TankState::Delayed;
+            tank_schedule.schedule(
+                us_timestamp + TANK_PUSH_DELAY,
+                (tank_ids[index], TankState::Idle),
+            );
+            continue;
+        }
+
         // do stuff
         let id = *id_counter;
         *id_counter += 1;
@@ -136,11 +342,192 @@ pub fn tank_push_command(
         sliding_block_types.push(b);
         static_block_types[(x, y)] = None;
 
-        tank_states[index] = TankState::Delayed {
-            timestamp: ms_timestamp,
-            duration: 1000,
-        };
+        // hold the tank still for a moment after the push
+        tank_states[index] = TankState::Delayed;
+        tank_schedule.schedule(
+            us_timestamp + TANK_PUSH_DELAY,
+            (tank_ids[index], TankState::Idle),
+        );
     }
+
+    (shakes, smashed)
+}
+
+// enemies up against a wall the player shook are stunned for a while
+pub fn wall_shake(
+    shakes: &[(usize, Direction)],
+    us_timestamp: u64,
+    width: usize,
+    height: usize,
+    tanks: usize,
+    tank_ids: &(impl Index<usize, Output = u32> + ?Sized),
+    tank_positions: &mut (impl IndexMut<usize, Output = Vec2f> + ?Sized),
+    tank_controllers: &(impl Index<usize, Output = Controller> + ?Sized),
+    tank_states: &mut (impl IndexMut<usize, Output = TankState> + ?Sized),
+    tank_schedule: &mut Scheduler<(u32, TankState)>,
+) {
+    for &(pusher, side) in shakes {
+        match tank_controllers[pusher] {
+            Controller::Player => {}
+            _ => continue,
+        }
+
+        for index in 0..tanks {
+            match (&tank_controllers[index], tank_states[index]) {
+                (Controller::Player, _) => continue,
+                (_, TankState::Exploding) | (_, TankState::Dead) => continue,
+                _ => {}
+            }
+
+            // within half a cell of the inside of the wall
+            let Vec2(x, y) = tank_positions[index];
+            let touching = match side {
+                Direction::Up => y < 24.,
+                Direction::Down => y > ((height - 2) * 16) as f32 - 8.,
+                Direction::Left => x < 24.,
+                Direction::Right => x > ((width - 2) * 16) as f32 - 8.,
+            };
+            if !touching {
+                continue;
+            }
+
+            tank_stun(
+                index,
+                us_timestamp,
+                tank_ids,
+                tank_positions,
+                tank_states,
+                tank_schedule,
+            );
+        }
+    }
+}
+
+// shots start where a push would land, in front of the tank
+pub fn tank_fire_command(
+    fire: impl Iterator<Item = usize>,
+    us_timestamp: u64,
+    tank_ids: &(impl Index<usize, Output = u32> + ?Sized),
+    tank_positions: &(impl Index<usize, Output = Vec2f> + ?Sized),
+    tank_directions: &(impl Index<usize, Output = Direction> + ?Sized),
+    tank_states: &mut (impl IndexMut<usize, Output = TankState> + ?Sized),
+    tank_schedule: &mut Scheduler<(u32, TankState)>,
+    id_counter: &mut u32,
+    projectile_ids: &mut Vec<u32>,
+    projectile_positions: &mut Vec<Vec2f>,
+    projectile_directions: &mut Vec<Direction>,
+    projectile_owners: &mut Vec<u32>,
+    projectile_speeds: &mut Vec<f32>,
+) {
+    for index in fire {
+        let pos = tank_positions[index];
+        let udir = tank_directions[index].vec2f();
+
+        let id = *id_counter;
+        *id_counter += 1;
+
+        projectile_ids.push(id);
+        projectile_positions.push(pos + Vec2(8., 8.) + udir * 9.);
+        projectile_directions.push(tank_directions[index]);
+        projectile_owners.push(tank_ids[index]);
+        projectile_speeds.push(PROJECTILE_SPEED);
+
+        // the tank has to reload before it does anything else
+        tank_states[index] = TankState::Delayed;
+        tank_schedule.schedule(
+            us_timestamp + TANK_FIRE_DELAY,
+            (tank_ids[index], TankState::Idle),
+        );
+    }
+}
+
+// every enemy still in one piece is stunned, returning how many
+pub fn stun_enemies(
+    tanks: usize,
+    us_timestamp: u64,
+    tank_ids: &(impl Index<usize, Output = u32> + ?Sized),
+    tank_positions: &mut (impl IndexMut<usize, Output = Vec2f> + ?Sized),
+    tank_controllers: &(impl Index<usize, Output = Controller> + ?Sized),
+    tank_states: &mut (impl IndexMut<usize, Output = TankState> + ?Sized),
+    tank_schedule: &mut Scheduler<(u32, TankState)>,
+) -> u8 {
+    let mut stunned = 0;
+
+    for index in 0..tanks {
+        match (&tank_controllers[index], tank_states[index]) {
+            (Controller::Player, _) => continue,
+            (_, TankState::Exploding) | (_, TankState::Dead) => continue,
+            _ => {}
+        }
+
+        tank_stun(
+            index,
+            us_timestamp,
+            tank_ids,
+            tank_positions,
+            tank_states,
+            tank_schedule,
+        );
+        stunned += 1;
+    }
+
+    stunned
+}
+
+// knocks a tank onto the nearest spot, out of whatever it was doing, until it comes to
+fn tank_stun(
+    index: usize,
+    us_timestamp: u64,
+    tank_ids: &(impl Index<usize, Output = u32> + ?Sized),
+    tank_positions: &mut (impl IndexMut<usize, Output = Vec2f> + ?Sized),
+    tank_states: &mut (impl IndexMut<usize, Output = TankState> + ?Sized),
+    tank_schedule: &mut Scheduler<(u32, TankState)>,
+) {
+    let Vec2(x, y) = tank_positions[index];
+    tank_positions[index] = Vec2((x / 8.).round() * 8., (y / 8.).round() * 8.);
+    let id = tank_ids[index];
+    tank_schedule.retain(|&(tank_id, _)| tank_id != id);
+    tank_schedule.schedule(us_timestamp + TANK_STUN_TIME, (id, TankState::Idle));
+    tank_states[index] = TankState::Stunned;
+}
+
+// players run over any stunned tanks they drive into, returning how many
+pub fn stunned_tank_collision(
+    tanks: usize,
+    us_timestamp: u64,
+    tank_ids: &(impl Index<usize, Output = u32> + ?Sized),
+    tank_positions: &(impl Index<usize, Output = Vec2f> + ?Sized),
+    tank_controllers: &(impl Index<usize, Output = Controller> + ?Sized),
+    tank_states: &mut (impl IndexMut<usize, Output = TankState> + ?Sized),
+    tank_schedule: &mut Scheduler<(u32, TankState)>,
+) -> u8 {
+    let mut kills = 0;
+
+    for player in 0..tanks {
+        match (&tank_controllers[player], tank_states[player]) {
+            (Controller::Player, TankState::Idle)
+            | (Controller::Player, TankState::Moving { .. }) => {}
+            _ => continue,
+        }
+
+        let Vec2(px, py) = tank_positions[player];
+        for index in 0..tanks {
+            match tank_states[index] {
+                TankState::Stunned => {}
+                _ => continue,
+            }
+
+            let Vec2(x, y) = tank_positions[index];
+            if (x - px).abs() >= 16. || (y - py).abs() >= 16. {
+                continue;
+            }
+
+            tank_destroy(index, us_timestamp, tank_ids, tank_states, tank_schedule);
+            kills += 1;
+        }
+    }
+
+    kills
 }
 
 pub fn tank_move_command(
@@ -150,80 +537,594 @@ pub fn tank_move_command(
     tank_directions: &mut (impl IndexMut<usize, Output = Direction> + ?Sized),
     tank_states: &mut (impl IndexMut<usize, Output = TankState> + ?Sized),
     static_block_types: &(impl Index<(usize, usize), Output = Option<BlockType>> + ?Sized),
+    terrain: &(impl Index<(usize, usize), Output = Terrain> + ?Sized),
 ) {
-    let ms_timestamp = (us_timestamp / 1000) as u16;
-
     for (index, dir) in mov {
-        let pos = tank_positions[index];
-        let udir = dir.vec2f();
-        let utan = Vec2(udir.1, udir.0);
-
-        let tar = pos + Vec2(8., 8.) + udir * 9.;
-        let Vec2(tx1, ty1) = tar + utan;
-        let Vec2(tx2, ty2) = tar - utan;
-
-        let b1 = static_block_types[(tx1 as usize / 16, ty1 as usize / 16)];
-        let b2 = static_block_types[(tx2 as usize / 16, ty2 as usize / 16)];
-
         // always set direction
         tank_directions[index] = dir;
 
-        match (b1, b2) {
-            (None, None) => {}
-            _ => continue,
+        if !tank_can_move(tank_positions[index], dir, static_block_types, terrain) {
+            continue;
         }
 
         // set moving state
         tank_states[index] = TankState::Moving {
-            timestamp: ms_timestamp,
-            duration: 400,
+            timer: Timer::new(us_timestamp, TANK_MOVE_TIME),
             start: tank_positions[index],
+            slid: 0,
         }
     }
 }
 
+// checks the two blocks directly in front of a tank at pos facing dir, and the ground under them
+pub fn tank_can_move(
+    pos: Vec2f,
+    dir: Direction,
+    static_block_types: &(impl Index<(usize, usize), Output = Option<BlockType>> + ?Sized),
+    terrain: &(impl Index<(usize, usize), Output = Terrain> + ?Sized),
+) -> bool {
+    let udir = dir.vec2f();
+    let utan = Vec2(udir.1, udir.0);
+
+    let tar = pos + Vec2(8., 8.) + udir * 9.;
+    [tar + utan, tar - utan].iter().all(|&Vec2(tx, ty)| {
+        let cell = (tx as usize / 16, ty as usize / 16);
+        if terrain[cell] == Terrain::Water {
+            return false;
+        }
+        match static_block_types[cell] {
+            None => true,
+            // one-way blocks can only be crossed along their own direction, but a
+            // tank that is already partway onto one can always back off of it the way it came
+            Some(BlockType::OneWay(one_way)) => {
+                one_way == dir || (one_way.opposite() == dir && tank_overlaps_cell(pos, cell))
+            }
+            Some(BlockType::TimeBonus) => true,
+            // bricks only block where they haven't been chipped away
+            Some(BlockType::Brick(mask)) => mask & brick_quarter(Vec2(tx, ty)) == 0,
+            Some(_) => false,
+        }
+    })
+}
+
+fn tank_overlaps_cell(pos: Vec2f, (x, y): (usize, usize)) -> bool {
+    let Vec2(cx, cy) = Vec2((x * 16) as f32, (y * 16) as f32);
+    pos.0 < cx + 16. && pos.0 + 16. > cx && pos.1 < cy + 16. && pos.1 + 16. > cy
+}
+
 pub fn tank_movement(
     tanks: usize,
     us_timestamp: u64,
     tank_positions: &mut (impl IndexMut<usize, Output = Vec2f> + ?Sized),
     tank_directions: &mut (impl IndexMut<usize, Output = Direction> + ?Sized),
     tank_states: &mut (impl IndexMut<usize, Output = TankState> + ?Sized),
+    static_block_types: &(impl Index<(usize, usize), Output = Option<BlockType>> + ?Sized),
+    terrain: &(impl Index<(usize, usize), Output = Terrain> + ?Sized),
 ) {
-    let ms_timestamp = (us_timestamp / 1000) as u16;
-
     for index in 0..tanks {
         // skip the non-moving tanks
-        let (timestamp, duration, start) = match tank_states[index] {
-            TankState::Moving {
-                timestamp,
-                duration,
-                start,
-            } => (timestamp, duration, start),
+        let (timer, start, slid) = match tank_states[index] {
+            TankState::Moving { timer, start, slid } => (timer, start, slid),
             _ => continue,
         };
 
         // calculate the position of the tank
-        let diff = (ms_timestamp).wrapping_sub(timestamp);
-        let ratio = clamp(0.0, diff as f32 / duration as f32, 1.0);
+        let ratio = timer.ratio(us_timestamp);
         let direction_vec = tank_directions[index].vec2f();
         tank_positions[index] = start + direction_vec * ratio * 8.0;
 
-        // transition the tank into idle maybe
-        if diff > duration {
-            tank_states[index] = TankState::Idle;
+        // transition the tank into idle maybe, unless it finished on ice and gets carried
+        // on another step, up to a cell past where it was headed
+        if timer.done(us_timestamp) {
+            let pos = tank_positions[index];
+            let Vec2(cx, cy) = pos + Vec2(8., 8.);
+            let dir = tank_directions[index];
+            let slide = slid < ICE_SLIDE_STEPS
+                && terrain[(cx as usize / 16, cy as usize / 16)] == Terrain::Ice
+                && tank_can_move(pos, dir, static_block_types, terrain);
+            tank_states[index] = match slide {
+                true => TankState::Moving {
+                    timer: Timer::new(timer.start + timer.duration, TANK_MOVE_TIME),
+                    start: pos,
+                    slid: slid + 1,
+                },
+                false => TankState::Idle,
+            };
         }
     }
 }
 
-pub fn sliding_block_movement(
+// player tanks collect any time bonuses they drive over, returning how many
+pub fn time_bonus_pickup(
+    tanks: usize,
+    tank_positions: &(impl Index<usize, Output = Vec2f> + ?Sized),
+    tank_controllers: &(impl Index<usize, Output = Controller> + ?Sized),
+    static_block_types: &mut (impl IndexMut<(usize, usize), Output = Option<BlockType>> + ?Sized),
+) -> u64 {
+    let mut pickups = 0;
+
+    for index in 0..tanks {
+        match tank_controllers[index] {
+            Controller::Player => {}
+            _ => continue,
+        }
+
+        // the up to 4 cells under the tank
+        let Vec2(x, y) = tank_positions[index];
+        let (x1, y1) = (x as usize / 16, y as usize / 16);
+        let (x2, y2) = ((x + 15.) as usize / 16, (y + 15.) as usize / 16);
+        for &cell in [(x1, y1), (x2, y1), (x1, y2), (x2, y2)].iter() {
+            if let Some(BlockType::TimeBonus) = static_block_types[cell] {
+                static_block_types[cell] = None;
+                pickups += 1;
+            }
+        }
+    }
+
+    pickups
+}
+
+// crushes every tank a sliding block runs into, returning how many of them were enemies
+pub fn sliding_block_tank_collision(
     sliding_blocks: usize,
-    dt: f32,
-    sliding_block_positions: &mut (impl IndexMut<usize, Output = Vec2f> + ?Sized),
-    sliding_block_directions: &(impl Index<usize, Output = Direction> + ?Sized),
+    tanks: usize,
+    us_timestamp: u64,
+    sliding_block_positions: &(impl Index<usize, Output = Vec2f> + ?Sized),
+    tank_ids: &(impl Index<usize, Output = u32> + ?Sized),
+    tank_positions: &(impl Index<usize, Output = Vec2f> + ?Sized),
+    tank_controllers: &(impl Index<usize, Output = Controller> + ?Sized),
+    tank_states: &mut (impl IndexMut<usize, Output = TankState> + ?Sized),
+    tank_shields: &(impl Index<usize, Output = u64> + ?Sized),
+    tank_schedule: &mut Scheduler<(u32, TankState)>,
+) -> u8 {
+    let mut kills = 0;
+
+    for tank_index in 0..tanks {
+        // skip tanks that are already destroyed or can't be
+        match tank_states[tank_index] {
+            TankState::Exploding | TankState::Dead => continue,
+            _ if tank_shields[tank_index] > us_timestamp => continue,
+            _ => {}
+        }
+
+        let Vec2(tx, ty) = tank_positions[tank_index];
+        for block_index in 0..sliding_blocks {
+            // both are 16x16, so any overlap at all is a hit
+            let Vec2(bx, by) = sliding_block_positions[block_index];
+            if (bx - tx).abs() >= 16. || (by - ty).abs() >= 16. {
+                continue;
+            }
+
+            tank_destroy(
+                tank_index,
+                us_timestamp,
+                tank_ids,
+                tank_states,
+                tank_schedule,
+            );
+            match tank_controllers[tank_index] {
+                Controller::Player => {}
+                _ => kills += 1,
+            }
+            break;
+        }
+    }
+
+    kills
+}
+
+pub fn tank_destroy(
+    index: usize,
+    us_timestamp: u64,
+    tank_ids: &(impl Index<usize, Output = u32> + ?Sized),
+    tank_states: &mut (impl IndexMut<usize, Output = TankState> + ?Sized),
+    tank_schedule: &mut Scheduler<(u32, TankState)>,
+) {
+    // drop whatever the tank was waiting on, and remove it once the explosion is over
+    let id = tank_ids[index];
+    tank_schedule.retain(|&(tank_id, _)| tank_id != id);
+    tank_schedule.schedule(us_timestamp + TANK_EXPLOSION_TIME, (id, TankState::Dead));
+    tank_states[index] = TankState::Exploding;
+}
+
+pub fn tank_cleanup(
+    tank_ids: &mut Vec<u32>,
+    tank_positions: &mut Vec<Vec2f>,
+    tank_directions: &mut Vec<Direction>,
+    tank_states: &mut Vec<TankState>,
+    tank_controllers: &mut Vec<Controller>,
+    tank_shields: &mut Vec<u64>,
 ) {
-    for index in 0..sliding_blocks {
-        let Vec2(dx, dy) = sliding_block_directions[index].vec2f();
-        let Vec2(x, y) = sliding_block_positions[index];
-        sliding_block_positions[index] = Vec2(x + 60. * dt * dx, y + 60. * dt * dy);
+    let mut index = 0;
+    while index < tank_ids.len() {
+        // skip tanks that are still around
+        match tank_states[index] {
+            TankState::Dead => {}
+            _ => {
+                index += 1;
+                continue;
+            }
+        }
+
+        // remove the tank
+        tank_ids.swap_remove(index);
+        tank_positions.swap_remove(index);
+        tank_directions.swap_remove(index);
+        tank_states.swap_remove(index);
+        tank_controllers.swap_remove(index);
+        tank_shields.swap_remove(index);
+    }
+}
+
+pub fn sliding_block_movement(
+    static_block_types: &mut Array2D<Option<BlockType>>,
+    terrain: &mut Array2D<Terrain>,
+    sliding_block_ids: &mut Vec<u32>,
+    sliding_block_positions: &mut Vec<Vec2f>,
+    sliding_block_directions: &mut Vec<Direction>,
+    sliding_block_types: &mut Vec<BlockType>,
+) -> Vec<(usize, usize)> {
+    let mut settled = vec![];
+    let mut index = 0;
+    while index < sliding_block_ids.len() {
+        let dir = sliding_block_directions[index];
+        let pos = sliding_block_positions[index];
+        let new_pos = pos + dir.vec2f() * SLIDING_BLOCK_SPEED;
+
+        // keep sliding until the block runs into something
+        let block_type = sliding_block_types[index];
+        let (x, y) =
+            match sliding_block_stop(static_block_types, terrain, pos, new_pos, dir, block_type) {
+                Some(cell) => cell,
+                None => {
+                    sliding_block_positions[index] = new_pos;
+                    index += 1;
+                    continue;
+                }
+            };
+
+        // sink into water, filling it in, or else settle the block back into the grid,
+        // chipping any brick it ran into
+        if terrain[(x, y)] == Terrain::Water {
+            terrain[(x, y)] = Terrain::Floor;
+        } else {
+            static_block_types[(x, y)] = Some(block_type);
+            settled.push((x, y));
+            let Vec2(dx, dy) = dir.vec2i();
+            brick_chip(
+                static_block_types,
+                ((x as i32 + dx) as usize, (y as i32 + dy) as usize),
+                dir,
+            );
+        }
+        sliding_block_ids.swap_remove(index);
+        sliding_block_positions.swap_remove(index);
+        sliding_block_directions.swap_remove(index);
+        sliding_block_types.swap_remove(index);
+    }
+
+    settled
+}
+
+// knocks the side of a brick facing something coming in along dir off, or the far
+// side once the near one has gone
+fn brick_chip(
+    static_block_types: &mut Array2D<Option<BlockType>>,
+    (x, y): (usize, usize),
+    dir: Direction,
+) {
+    let mask = match static_block_types.coord_get(x, y) {
+        Some(&Some(BlockType::Brick(mask))) => mask,
+        _ => return,
+    };
+
+    // the two quarters of each side, nearest first
+    let (near, far) = match dir {
+        Direction::Right => (0b0101, 0b1010),
+        Direction::Left => (0b1010, 0b0101),
+        Direction::Down => (0b0011, 0b1100),
+        Direction::Up => (0b1100, 0b0011),
+    };
+    let mask = match mask & near {
+        0 => mask & !far,
+        _ => mask & !near,
+    };
+    brick_set(static_block_types, (x, y), mask);
+}
+
+// an empty brick is no brick at all
+fn brick_set(static_block_types: &mut Array2D<Option<BlockType>>, cell: (usize, usize), mask: u8) {
+    static_block_types[cell] = match mask {
+        0 => None,
+        _ => Some(BlockType::Brick(mask)),
+    };
+}
+
+// lines of three or more diamonds running through any of the cells blocks just settled
+// into, each counted once however many of its blocks arrived together. a line that was
+// already three long before they arrived has been counted, and only grew.
+pub fn diamond_lines(
+    static_block_types: &Array2D<Option<BlockType>>,
+    settled: &[(usize, usize)],
+) -> u32 {
+    let diamond = |i: i32, j: i32| {
+        i >= 0
+            && j >= 0
+            && match static_block_types.coord_get(i as usize, j as usize) {
+                Some(Some(BlockType::Diamond)) => true,
+                _ => false,
+            }
+    };
+
+    // each line is known by its first cell and whether it runs across
+    let mut lines = vec![];
+    for &(x, y) in settled {
+        let (x, y) = (x as i32, y as i32);
+        if !diamond(x, y) {
+            continue;
+        }
+
+        for &(dx, dy) in [(1, 0), (0, 1)].iter() {
+            let (mut start, mut end) = (0, 0);
+            while diamond(x + (start - 1) * dx, y + (start - 1) * dy) {
+                start -= 1;
+            }
+            while diamond(x + (end + 1) * dx, y + (end + 1) * dy) {
+                end += 1;
+            }
+
+            // the longest stretch of it there was before this settle
+            let mut before = 0;
+            let mut run = 0;
+            for k in start..=end {
+                let cell = ((x + k * dx) as usize, (y + k * dy) as usize);
+                run = match settled.contains(&cell) {
+                    true => 0,
+                    false => run + 1,
+                };
+                before = before.max(run);
+            }
+
+            let line = (x + start * dx, y + start * dy, dx == 1);
+            if end - start + 1 >= 3 && before < 3 && !lines.contains(&line) {
+                lines.push(line);
+            }
+        }
+    }
+
+    lines.len() as u32
+}
+
+// walks the cells a sliding block's leading edge enters between from and to,
+// returning the last free cell if the block runs into something on the way,
+// or the first water it comes to. diamonds and eggs don't sink, and stop short
+// of water the same as a wall.
+pub fn sliding_block_stop(
+    static_block_types: &Array2D<Option<BlockType>>,
+    terrain: &Array2D<Terrain>,
+    from: Vec2f,
+    to: Vec2f,
+    dir: Direction,
+    block_type: BlockType,
+) -> Option<(usize, usize)> {
+    let Vec2(fx, fy) = Vec2(from.0 / 16., from.1 / 16.);
+    let Vec2(tx, ty) = Vec2(to.0 / 16., to.1 / 16.);
+
+    // the cell the block last fully occupied, and the furthest cell it now overlaps
+    let (start, end) = match dir {
+        Direction::Right => ((fx.floor(), fy.round()), (tx.ceil(), fy.round())),
+        Direction::Left => ((fx.ceil(), fy.round()), (tx.floor(), fy.round())),
+        Direction::Down => ((fx.round(), fy.floor()), (fx.round(), ty.ceil())),
+        Direction::Up => ((fx.round(), fy.ceil()), (fx.round(), ty.floor())),
+    };
+    let end = (end.0 as i32, end.1 as i32);
+    let Vec2(dx, dy) = dir.vec2i();
+
+    let mut cell = (start.0 as i32, start.1 as i32);
+    while cell != end {
+        let next = (cell.0 + dx, cell.1 + dy);
+
+        // anything outside of the map counts as a wall
+        let free = next.0 >= 0
+            && next.1 >= 0
+            && match static_block_types.coord_get(next.0 as usize, next.1 as usize) {
+                Some(None) => true,
+                _ => false,
+            };
+        if !free {
+            return Some((cell.0 as usize, cell.1 as usize));
+        }
+        if terrain[(next.0 as usize, next.1 as usize)] == Terrain::Water {
+            return match block_type {
+                BlockType::Diamond | BlockType::Egg => Some((cell.0 as usize, cell.1 as usize)),
+                _ => Some((next.0 as usize, next.1 as usize)),
+            };
+        }
+
+        cell = next;
+    }
+
+    None
+}
+
+// projectiles fly straight on until they hit a block, moving or not, or leave the map.
+// pickups don't stop them, bricks lose the quarter that was hit, and a base is destroyed,
+// returning whether one was
+pub fn projectile_movement(
+    static_block_types: &mut Array2D<Option<BlockType>>,
+    sliding_block_positions: &[Vec2f],
+    projectile_ids: &mut Vec<u32>,
+    projectile_positions: &mut Vec<Vec2f>,
+    projectile_directions: &mut Vec<Direction>,
+    projectile_owners: &mut Vec<u32>,
+    projectile_speeds: &mut Vec<f32>,
+) -> bool {
+    let mut base_destroyed = false;
+
+    let mut index = 0;
+    while index < projectile_ids.len() {
+        let pos = projectile_positions[index]
+            + projectile_directions[index].vec2f() * projectile_speeds[index];
+        projectile_positions[index] = pos;
+
+        // positions are the middle of the shot, so a point test is enough
+        let Vec2(x, y) = pos;
+        let cell = (x as usize / 16, y as usize / 16);
+        let hit = x < 0.
+            || y < 0.
+            || match static_block_types.coord_get(cell.0, cell.1) {
+                Some(None) | Some(Some(BlockType::TimeBonus)) => false,
+                Some(&Some(BlockType::Brick(mask))) => {
+                    let quarter = brick_quarter(pos);
+                    if mask & quarter != 0 {
+                        brick_set(static_block_types, cell, mask & !quarter);
+                    }
+                    mask & quarter != 0
+                }
+                Some(Some(BlockType::Base)) => {
+                    static_block_types[cell] = None;
+                    base_destroyed = true;
+                    true
+                }
+                _ => true,
+            }
+            || sliding_block_positions
+                .iter()
+                .any(|&Vec2(bx, by)| x >= bx && x < bx + 16. && y >= by && y < by + 16.);
+
+        if hit {
+            projectile_remove(
+                index,
+                projectile_ids,
+                projectile_positions,
+                projectile_directions,
+                projectile_owners,
+                projectile_speeds,
+            );
+        } else {
+            index += 1;
+        }
+    }
+
+    base_destroyed
+}
+
+// shots destroy the first tank they hit, other than the one that fired them,
+// and are used up on a shielded tank without harming it. returns how many
+// enemies the player shot
+pub fn projectile_tank_collision(
+    tanks: usize,
+    us_timestamp: u64,
+    projectile_ids: &mut Vec<u32>,
+    projectile_positions: &mut Vec<Vec2f>,
+    projectile_directions: &mut Vec<Direction>,
+    projectile_owners: &mut Vec<u32>,
+    projectile_speeds: &mut Vec<f32>,
+    tank_ids: &(impl Index<usize, Output = u32> + ?Sized),
+    tank_positions: &(impl Index<usize, Output = Vec2f> + ?Sized),
+    tank_controllers: &(impl Index<usize, Output = Controller> + ?Sized),
+    tank_states: &mut (impl IndexMut<usize, Output = TankState> + ?Sized),
+    tank_shields: &(impl Index<usize, Output = u64> + ?Sized),
+    tank_schedule: &mut Scheduler<(u32, TankState)>,
+) -> u8 {
+    let mut kills = 0;
+
+    let mut index = 0;
+    while index < projectile_ids.len() {
+        let Vec2(x, y) = projectile_positions[index];
+        let target = (0..tanks).find(|&tank_index| {
+            let Vec2(tx, ty) = tank_positions[tank_index];
+            match tank_states[tank_index] {
+                TankState::Exploding | TankState::Dead => false,
+                _ => {
+                    tank_ids[tank_index] != projectile_owners[index]
+                        && x >= tx
+                        && x < tx + 16.
+                        && y >= ty
+                        && y < ty + 16.
+                }
+            }
+        });
+
+        let tank_index = match target {
+            Some(tank_index) => tank_index,
+            None => {
+                index += 1;
+                continue;
+            }
+        };
+
+        if tank_shields[tank_index] <= us_timestamp {
+            tank_destroy(
+                tank_index,
+                us_timestamp,
+                tank_ids,
+                tank_states,
+                tank_schedule,
+            );
+
+            // only the player's shots count, and only against enemies
+            let player = |index: usize| match tank_controllers[index] {
+                Controller::Player => true,
+                _ => false,
+            };
+            let owner = (0..tanks).find(|&owner| tank_ids[owner] == projectile_owners[index]);
+            if owner.map_or(false, player) && !player(tank_index) {
+                kills += 1;
+            }
+        }
+        projectile_remove(
+            index,
+            projectile_ids,
+            projectile_positions,
+            projectile_directions,
+            projectile_owners,
+            projectile_speeds,
+        );
+    }
+
+    kills
+}
+
+fn projectile_remove(
+    index: usize,
+    projectile_ids: &mut Vec<u32>,
+    projectile_positions: &mut Vec<Vec2f>,
+    projectile_directions: &mut Vec<Direction>,
+    projectile_owners: &mut Vec<u32>,
+    projectile_speeds: &mut Vec<f32>,
+) {
+    projectile_ids.swap_remove(index);
+    projectile_positions.swap_remove(index);
+    projectile_directions.swap_remove(index);
+    projectile_owners.swap_remove(index);
+    projectile_speeds.swap_remove(index);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_way_only_backs_off() {
+        // a right arrow in the middle, with the tank straddling it and the row above
+        let mut static_block_types = Array2D::from_closure(5, 5, |_, _| None);
+        static_block_types[(2, 2)] = Some(BlockType::OneWay(Direction::Right));
+        let terrain = Array2D::from_closure(5, 5, |_, _| Terrain::Floor);
+        let can_move = |pos, dir| tank_can_move(pos, dir, &static_block_types, &terrain);
+
+        // in along the arrow
+        assert!(can_move(Vec2(16., 24.), Direction::Right));
+
+        // then partway on, it can carry on, but not turn across it
+        let on = Vec2(32., 24.);
+        assert!(can_move(on, Direction::Right));
+        assert!(!can_move(on, Direction::Down));
+
+        // halfway off the far side, it can still back over it
+        assert!(can_move(Vec2(40., 32.), Direction::Left));
+
+        // and never in against the arrow
+        assert!(!can_move(Vec2(48., 32.), Direction::Left));
     }
 }
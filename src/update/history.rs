@@ -0,0 +1,60 @@
+use super::snapshot::Snapshot;
+use std::collections::VecDeque;
+
+// how many player moves and pushes can be taken back
+pub const UNDO_LEVELS: usize = 100;
+
+// how far back rewinding can go, one state per tick
+pub const REWIND_TICKS: usize = 10 * super::TICKS_PER_SECOND as usize;
+
+// past states as encoded snapshots, tagged with their clock so the two lists can be kept
+// from disagreeing. the rewind buffer has every tick, the undo stack only the ticks a
+// player command started on.
+pub struct History {
+    undo: Vec<(u64, Vec<u8>)>,
+    rewind: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            undo: vec![],
+            rewind: VecDeque::with_capacity(REWIND_TICKS),
+        }
+    }
+
+    // called at the start of each simulated tick
+    pub fn record(&mut self, snapshot: &Snapshot) {
+        if self.rewind.len() == REWIND_TICKS {
+            self.rewind.pop_front();
+        }
+        self.rewind.push_back((snapshot.clock, snapshot.to_bytes()));
+    }
+
+    // marks the last recorded tick as one to undo back to
+    pub fn checkpoint(&mut self) {
+        if let Some(entry) = self.rewind.back() {
+            if self.undo.len() == UNDO_LEVELS {
+                self.undo.remove(0);
+            }
+            self.undo.push(entry.clone());
+        }
+    }
+
+    pub fn undo(&mut self) -> Option<Snapshot> {
+        let (clock, bytes) = self.undo.pop()?;
+        self.rewind.retain(|&(at, _)| at < clock);
+        Snapshot::from_bytes(&bytes).ok()
+    }
+
+    pub fn rewind(&mut self) -> Option<Snapshot> {
+        let (clock, bytes) = self.rewind.pop_back()?;
+        self.undo.retain(|&(at, _)| at < clock);
+        Snapshot::from_bytes(&bytes).ok()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.rewind.clear();
+    }
+}
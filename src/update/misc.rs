@@ -1,10 +1,42 @@
+use super::timer::Timer;
 use crate::common::*;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum BlockType {
     Normal,
     Solid,
     OneWay(Direction),
+    // picked up by driving over it, for extra time on the clock
+    TimeBonus,
+    // pushed like a normal block, but never broken. three in a line pay out
+    Diamond,
+    // a dormant enemy, breaking open on the level's hatch schedule
+    Egg,
+    // chipped away by shots and sliding blocks, a quarter at a time. one bit per 8x8
+    // quarter still standing: top left, top right, bottom left, bottom right
+    Brick(u8),
+    // what the player is defending. one shot from anyone and the round is lost
+    Base,
+}
+
+pub const BRICK_WHOLE: u8 = 0b1111;
+
+// the ground under the blocks
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Terrain {
+    Floor,
+    // stops tanks, and swallows sliding blocks, turning into floor
+    Water,
+    // carries tanks on past where they meant to stop
+    Ice,
+    // hides the tanks under it
+    Trees,
+}
+
+// the brick quarter a point in the world falls in
+pub fn brick_quarter(Vec2(x, y): Vec2f) -> u8 {
+    let (qx, qy) = (x as usize % 16 / 8, y as usize % 16 / 8);
+    1 << (qx + 2 * qy)
 }
 
 // this needs to change
@@ -13,21 +45,85 @@ pub fn block_to_uv(block_type: BlockType) -> Vec2f {
         BlockType::Normal => Vec2(32., 0.),
         BlockType::Solid => Vec2(16., 0.),
         BlockType::OneWay(direction) => match direction {
-            _ => unimplemented!(),
+            Direction::Up => Vec2(0., 32.),
+            Direction::Down => Vec2(16., 32.),
+            Direction::Left => Vec2(32., 32.),
+            Direction::Right => Vec2(48., 32.),
         },
+        BlockType::TimeBonus => Vec2(0., 48.),
+        BlockType::Diamond => Vec2(32., 48.),
+        BlockType::Egg => Vec2(48., 48.),
+        BlockType::Brick(_) => Vec2(0., 16.),
+        BlockType::Base => Vec2(48., 64.),
     }
 }
 
+// floor is left as the background
+pub fn terrain_to_uv(terrain: Terrain) -> Option<Vec2f> {
+    match terrain {
+        Terrain::Floor => None,
+        Terrain::Water => Some(Vec2(0., 64.)),
+        Terrain::Ice => Some(Vec2(16., 64.)),
+        Terrain::Trees => Some(Vec2(32., 64.)),
+    }
+}
+
+// the corner of a 4x4 sprite
+pub const PROJECTILE_UV: Vec2f = Vec2(48., 0.);
+
 #[derive(Copy, Clone, Debug)]
 pub enum TankState {
     Idle,
+    // slid counts the steps ice has carried it on for
     Moving {
-        timestamp: u16, // milliseconds
-        duration: u16,  // milliseconds
+        timer: Timer,
         start: Vec2f,
+        slid: u8,
     },
-    Delayed {
-        timestamp: u16, // milliseconds
-        duration: u16,  // milliseconds
-    },
+    // waiting on a scheduled transition back to idle
+    Delayed,
+    // knocked out by a wall shake, waiting on a scheduled transition back to idle
+    Stunned,
+    // waiting on a scheduled transition to dead
+    Exploding,
+    // waiting to be removed
+    Dead,
+}
+
+pub fn tank_to_uv(tank_state: TankState) -> Vec2f {
+    match tank_state {
+        TankState::Exploding => Vec2(48., 16.),
+        TankState::Stunned => Vec2(16., 48.),
+        _ => Vec2(32., 16.),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Controller {
+    // driven by the keyboard
+    Player,
+    // rolls around at random, kicking blocks now and then
+    Wander,
+    // loops through a fixed list of moves
+    Patrol { route: Vec<Direction>, step: usize },
+    // heads for the nearest player tank
+    Chase,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PlayerInput {
+    pub push: bool,
+    pub fire: bool,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GameState {
+    Playing,
+    GameOver,
+    // every wave has been beaten
+    Won,
 }
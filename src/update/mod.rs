@@ -1,13 +1,25 @@
 use crate::common::*;
+use crate::generate::Endless;
+use crate::level::Level;
 use crate::render::render_state::RenderState;
 use crossbeam_channel::Receiver;
 use crossbeam_channel::Sender;
 use event::Event;
+use replay::Replay;
+use std::path::PathBuf;
 use update::State;
 
+mod binary;
+mod editor;
 pub mod event;
 mod func;
+mod history;
 pub mod misc;
+mod path;
+pub mod replay;
+mod snapshot;
+pub mod solver;
+pub mod timer;
 mod update;
 
 pub fn time<T>(mut func: impl FnMut() -> T) -> (u32, T) {
@@ -17,40 +29,140 @@ pub fn time<T>(mut func: impl FnMut() -> T) -> (u32, T) {
     (end.wrapping_sub(start), ret)
 }
 
-pub fn update_thread(render_s: Sender<RenderState>, input_r: Receiver<Event>) {
+// the simulation only ever advances in whole ticks of this length
+pub const TICKS_PER_SECOND: u64 = 60;
+pub const US_PER_TICK: u64 = 1_000_000 / TICKS_PER_SECOND;
+
+// a script has one event per line, after the tick it happens on:
+//   ; hold right for half a second, then push
+//   0 press right
+//   30 release right
+//   31 press z
+pub fn parse_script(src: &str) -> Result<Vec<(u64, Event)>, String> {
+    let mut script = vec![];
+    for (index, line) in src.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut words = line.splitn(2, char::is_whitespace);
+        let tick = words.next().unwrap_or("");
+        let tick = match tick.parse() {
+            Ok(tick) => tick,
+            Err(_) => return Err(format!("line {}: \"{}\" is not a tick", index + 1, tick)),
+        };
+        let event = words.next().unwrap_or("").trim();
+        match Event::parse(event) {
+            Some(event) => script.push((tick, event)),
+            None => return Err(format!("line {}: \"{}\" is not an event", index + 1, event)),
+        }
+    }
+    Ok(script)
+}
+
+// runs a level without a window for some number of ticks, returning where everything ended up
+pub fn run_headless(level: &Level, script: &[(u64, Event)], ticks: u64) -> String {
+    let mut state = State::from_level(level);
+    for tick in 0..ticks {
+        let events = script
+            .iter()
+            .filter(|&&(event_tick, _)| event_tick == tick)
+            .map(|&(_, event)| event);
+        state.pre_step(events);
+        state.step();
+
+        if state.exit() {
+            break;
+        }
+    }
+    state.dump()
+}
+
+// where the update thread's events come from
+pub enum Input {
+    Live,
+    // live, and written to a replay file on exit
+    Record(PathBuf),
+    // events come from a replay instead of the window, which only gets to close it
+    Playback(Replay),
+}
+
+pub fn update_thread(
+    level: Level,
+    input: Input,
+    mut endless: Option<Endless>,
+    render_s: Sender<RenderState>,
+    input_r: Receiver<Event>,
+) {
     // frametime stuff
     let mut frametimes: Vec<Vec4<u32>> = Vec::new();
 
     // state setup
-    let mut state = State::new();
+    let mut state = State::from_level(&level);
+    let mut recording = match input {
+        Input::Record(_) => Some(Replay::new(&level)),
+        _ => None,
+    };
 
-    // the most amount of time an update can simulate
+    // the most amount of time a frame can try to catch up on
     let max_time_per_update = 250_000; // microseconds, 250ms
 
     // loop time
-    let mut game_timestamp = crate::time::get_microseconds_as_u64();
+    let mut real_timestamp = crate::time::get_microseconds_as_u64();
+    let mut accumulator = 0;
     loop {
         // pre-step
-        let (t1, _) = time(|| state.pre_step(game_timestamp, input_r.try_iter()));
+        let (t1, _) = time(|| {
+            let events: Vec<Event> = input_r.try_iter().collect();
+            match &input {
+                Input::Playback(_) => {
+                    state.pre_step(events.into_iter().filter(|&event| event == Event::Exit))
+                }
+                _ => {
+                    if let Some(recording) = &mut recording {
+                        recording.record(state.tick(), &events);
+                    }
+                    state.pre_step(events.into_iter());
+                }
+            }
+        });
 
-        // step (part 1)
+        // step as many whole ticks as real time allows
         let (t2, _) = time(|| {
-            let real_timestamp = crate::time::get_microseconds_as_u64();
-            while real_timestamp - game_timestamp > max_time_per_update {
-                state.step(game_timestamp, max_time_per_update);
-                game_timestamp += max_time_per_update;
-            }
+            let now = crate::time::get_microseconds_as_u64();
+            accumulator = (accumulator + now - real_timestamp).min(max_time_per_update);
+            real_timestamp = now;
+
+            while accumulator >= US_PER_TICK {
+                // recorded events go in right before the tick they were first seen on
+                if let Input::Playback(replay) = &input {
+                    state.pre_step(replay.events_at(state.tick()));
+                }
+                state.step();
+                accumulator -= US_PER_TICK;
 
-            // step (part 2)
-            state.step(game_timestamp, real_timestamp - game_timestamp);
-            game_timestamp = real_timestamp;
+                // endless mode moves straight on to another level once this one's beaten,
+                // or as soon after as the next one has been found
+                if let Some(endless) = &mut endless {
+                    if state.cleared() {
+                        if let Some(level) = endless.try_next_level() {
+                            state.load_level(&level);
+                        }
+                    }
+                }
+            }
         });
 
         // post-step
-        let (t3, _) = time(|| state.post_step(game_timestamp));
+        let (t3, _) = time(|| state.post_step());
 
-        // render prep
-        let (t4, rs) = time(|| state.render_prep());
+        // render prep, partway between this tick and the next
+        let alpha = match state.paused() {
+            true => 0.,
+            false => accumulator as f32 / US_PER_TICK as f32,
+        };
+        let (t4, rs) = time(|| state.render_prep(alpha));
 
         // time
         frametimes.push(Vec4(t1, t2, t3, t4));
@@ -75,6 +187,16 @@ pub fn update_thread(render_s: Sender<RenderState>, input_r: Receiver<Event>) {
             frametimes.clear();
         }
 
+        // write the recording out before the render thread takes the window down
+        if state.exit() {
+            if let (Input::Record(path), Some(recording)) = (&input, recording.take()) {
+                match recording.save(path) {
+                    Ok(()) => println!("Saved replay to {}", path.display()),
+                    Err(err) => eprintln!("{}: {}", path.display(), err),
+                }
+            }
+        }
+
         // send render state and wait
         render_s.send(rs).unwrap();
 
@@ -84,3 +206,23 @@ pub fn update_thread(render_s: Sender<RenderState>, input_r: Receiver<Event>) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headless_script() {
+        let level = Level::parse(include_str!("../../resources/levels/02.lvl")).unwrap();
+        let script = parse_script(include_str!("../../resources/scripts/02.txt")).unwrap();
+        let dump = run_headless(&level, &script, 240);
+
+        // the first block has been kicked along the top row into the next one
+        assert!(dump.starts_with("tick 240\n"));
+        assert!(dump.contains("state Playing\n"));
+        assert!(dump.contains("\n#.....oo.#\n"));
+
+        // and the same input always plays out the same way
+        assert_eq!(run_headless(&level, &script, 240), dump);
+    }
+}
@@ -0,0 +1,77 @@
+use super::func::tank_can_move;
+use super::misc::*;
+use crate::array2d::*;
+use crate::common::*;
+use std::collections::VecDeque;
+
+// breadth first search for the shortest list of 8px tank moves from one position to another
+pub fn find_path(
+    static_block_types: &Array2D<Option<BlockType>>,
+    terrain: &Array2D<Terrain>,
+    from: Vec2f,
+    to: Vec2f,
+) -> Option<Vec<Direction>> {
+    // nodes are the top left corners a 16x16 tank can sit at, every 8px
+    let width = (static_block_types.get_width() * 2).saturating_sub(1);
+    let height = (static_block_types.get_height() * 2).saturating_sub(1);
+    let node = |Vec2(x, y): Vec2f| {
+        let (i, j) = ((x / 8.).round(), (y / 8.).round());
+        match i >= 0. && j >= 0. && (i as usize) < width && (j as usize) < height {
+            true => Some((i as usize, j as usize)),
+            false => None,
+        }
+    };
+    let start = node(from)?;
+    let goal = node(to)?;
+
+    // the move that first reached each node
+    let mut came_from: Array2D<Option<Direction>> =
+        Array2D::from_closure(width, height, |_, _| None);
+    let mut visited = Array2D::from_closure(width, height, |i, j| (i, j) == start);
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+
+    while let Some((i, j)) = queue.pop_front() {
+        if (i, j) == goal {
+            break;
+        }
+
+        for &dir in Direction::ALL.iter() {
+            let Vec2(dx, dy) = dir.vec2i();
+            let (ni, nj) = (i as i32 + dx, j as i32 + dy);
+            if ni < 0 || nj < 0 || ni as usize >= width || nj as usize >= height {
+                continue;
+            }
+            let next = (ni as usize, nj as usize);
+            if visited[next] {
+                continue;
+            }
+
+            // same rules as tank_move_command
+            let pos = Vec2((i * 8) as f32, (j * 8) as f32);
+            if !tank_can_move(pos, dir, static_block_types, terrain) {
+                continue;
+            }
+
+            visited[next] = true;
+            came_from[next] = Some(dir);
+            queue.push_back(next);
+        }
+    }
+
+    // walk back from the goal
+    if !visited[goal] {
+        return None;
+    }
+    let mut path = vec![];
+    let mut cur = goal;
+    while cur != start {
+        let dir = came_from[cur]?;
+        let Vec2(dx, dy) = dir.vec2i();
+        cur = ((cur.0 as i32 - dx) as usize, (cur.1 as i32 - dy) as usize);
+        path.push(dir);
+    }
+    path.reverse();
+
+    Some(path)
+}
@@ -0,0 +1,180 @@
+use super::binary::*;
+use super::event::{Event, Key};
+use std::io::Result;
+use std::path::Path;
+
+// replay files start with this, then a version byte
+const MAGIC: &[u8; 4] = b"BTRP";
+const VERSION: u8 = 1;
+
+// every event the update thread saw, and the tick it was handed to the state on.
+// the level is kept as text so a replay doesn't depend on the level file staying the same.
+pub struct Replay {
+    pub level: String,
+    pub events: Vec<(u64, Event)>,
+}
+
+// quick saves and loads go to whatever is on disk at the time, so replaying them
+// wouldn't play out the same
+fn replayable(event: Event) -> bool {
+    match event {
+        Event::KeyDown(Key::F5) | Event::KeyDown(Key::F9) => false,
+        _ => true,
+    }
+}
+
+impl Replay {
+    pub fn new(level: &crate::level::Level) -> Self {
+        Self {
+            level: level.to_string(),
+            events: vec![],
+        }
+    }
+
+    pub fn record(&mut self, tick: u64, events: &[Event]) {
+        self.events.extend(
+            events
+                .iter()
+                .filter(|&&event| replayable(event))
+                .map(|&event| (tick, event)),
+        );
+    }
+
+    // the events that were fed in on a tick, less any quick saves or loads older
+    // replays picked up
+    pub fn events_at(&self, tick: u64) -> impl Iterator<Item = Event> + '_ {
+        use std::cmp::Ordering;
+        let start = self
+            .events
+            .binary_search_by(|&(t, _)| match t < tick {
+                true => Ordering::Less,
+                false => Ordering::Greater,
+            })
+            .unwrap_or_else(|index| index);
+        self.events[start..]
+            .iter()
+            .take_while(move |&&(t, _)| t == tick)
+            .map(|&(_, event)| event)
+            .filter(|&event| replayable(event))
+    }
+
+    // the tick after the last event
+    pub fn end(&self) -> u64 {
+        self.events.last().map_or(0, |&(tick, _)| tick + 1)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    // layout:
+    //   magic, version, level text, event count (u32),
+    //   then per event: ticks since the last event (varint), a tag byte and its payload
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Writer::new();
+        out.bytes(MAGIC);
+        out.u8(VERSION);
+        out.str(&self.level);
+        out.u32(self.events.len() as u32);
+
+        let mut last_tick = 0;
+        for &(tick, event) in &self.events {
+            out.varint(tick - last_tick);
+            last_tick = tick;
+
+            match event {
+                Event::KeyDown(key) => out.bytes(&[0, key.index()]),
+                Event::KeyUp(key) => out.bytes(&[1, key.index()]),
+                Event::MouseMove(x, y) => {
+                    out.u8(2);
+                    out.f32(x);
+                    out.f32(y);
+                }
+                Event::Resize(w, h) => {
+                    out.u8(3);
+                    out.f32(w);
+                    out.f32(h);
+                }
+                Event::Exit => out.u8(4),
+                Event::Invalid => out.u8(5),
+            }
+        }
+
+        out.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader(bytes);
+        reader.header(MAGIC, VERSION, "replay")?;
+        let level = reader.str()?;
+
+        // every event takes at least two bytes, so the count can't be trusted any further
+        let count = reader.u32()?;
+        let mut events = Vec::with_capacity((count as usize).min(reader.0.len() / 2));
+        let mut tick: u64 = 0;
+        for _ in 0..count {
+            tick = tick
+                .checked_add(reader.varint()?)
+                .ok_or_else(|| invalid("event ticks run past the end of time"))?;
+            let event = match reader.u8()? {
+                0 => Event::KeyDown(key(&mut reader)?),
+                1 => Event::KeyUp(key(&mut reader)?),
+                2 => Event::MouseMove(reader.f32()?, reader.f32()?),
+                3 => Event::Resize(reader.f32()?, reader.f32()?),
+                4 => Event::Exit,
+                5 => Event::Invalid,
+                tag => return Err(invalid(format!("unknown event tag {}", tag))),
+            };
+            events.push((tick, event));
+        }
+
+        Ok(Self { level, events })
+    }
+}
+
+fn key(reader: &mut Reader) -> Result<Key> {
+    let index = reader.u8()?;
+    Key::from_index(index).ok_or_else(|| invalid(format!("unknown key {}", index)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let level =
+            crate::level::Level::parse("tank 1 1 up player\nmap 3 3\n###\n#.#\n###").unwrap();
+        let mut replay = Replay::new(&level);
+        replay.record(0, &[Event::Resize(800., 720.), Event::KeyDown(Key::Right)]);
+        replay.record(
+            200,
+            &[Event::KeyDown(Key::F9), Event::MouseMove(1.5, -2.25)],
+        );
+        replay.record(
+            70_000,
+            &[Event::KeyUp(Key::Right), Event::Invalid, Event::Exit],
+        );
+
+        let read = Replay::from_bytes(&replay.to_bytes()).unwrap();
+        assert_eq!(read.level, replay.level);
+        assert_eq!(read.events, replay.events);
+        assert_eq!(
+            read.events_at(200).collect::<Vec<_>>(),
+            vec![Event::MouseMove(1.5, -2.25)]
+        );
+        assert!(Replay::from_bytes(&replay.to_bytes()[..20]).is_err());
+
+        // any one byte gone bad gives either an error or a replay, never a panic
+        let bytes = replay.to_bytes();
+        for index in 0..bytes.len() {
+            let mut bad = bytes.clone();
+            bad[index] = 0xff;
+            let _ = Replay::from_bytes(&bad);
+        }
+    }
+}
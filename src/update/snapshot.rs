@@ -0,0 +1,516 @@
+use super::binary::*;
+use super::misc::*;
+use super::timer::Timer;
+use crate::array2d::*;
+use crate::common::*;
+use crate::level::{TankSpawn, Wave};
+use std::io::Result;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"BTSV";
+const VERSION: u8 = 7;
+
+// everything the simulation needs to carry on from a moment, but none of the input
+// (keys, mouse, pending events) or the tick counter, which keep going across a load
+pub struct Snapshot {
+    pub clock: u64,
+    pub game_state: GameState,
+    pub camera: Vec4f,
+    pub time_limit: u64,
+    pub rem_time: u64,
+    pub rem_lives: u8,
+    pub rem_tanks: u8,
+    pub kills: u32,
+    pub score: u32,
+    pub lives: u8,
+    pub player_spawn: Vec2f,
+    pub tank_spawns: Vec<TankSpawn>,
+    pub hatch: Vec<u64>,
+    pub spawn_points: Vec<(usize, usize)>,
+    pub waves: Vec<Wave>,
+    pub wave_queue: Vec<Controller>,
+
+    pub static_block_types: Array2D<Option<BlockType>>,
+    pub terrain: Array2D<Terrain>,
+    pub id_counter: u32,
+
+    pub sliding_block_ids: Vec<u32>,
+    pub sliding_block_positions: Vec<Vec2f>,
+    pub sliding_block_directions: Vec<Direction>,
+    pub sliding_block_types: Vec<BlockType>,
+
+    pub projectile_ids: Vec<u32>,
+    pub projectile_positions: Vec<Vec2f>,
+    pub projectile_directions: Vec<Direction>,
+    pub projectile_owners: Vec<u32>,
+    pub projectile_speeds: Vec<f32>,
+
+    pub tank_ids: Vec<u32>,
+    pub tank_positions: Vec<Vec2f>,
+    pub tank_directions: Vec<Direction>,
+    pub tank_states: Vec<TankState>,
+    pub tank_controllers: Vec<Controller>,
+    pub tank_shields: Vec<u64>,
+    pub tank_schedule: Vec<(u64, (u32, TankState))>,
+
+    pub rng: u64,
+}
+
+impl Snapshot {
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    // fields in declaration order, each list prefixed by its length (u32)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Writer::new();
+        out.bytes(MAGIC);
+        out.u8(VERSION);
+
+        out.u64(self.clock);
+        out.u8(match self.game_state {
+            GameState::Playing => 0,
+            GameState::GameOver => 1,
+            GameState::Won => 2,
+        });
+        let Vec4(x, y, w, h) = self.camera;
+        out.f32(x);
+        out.f32(y);
+        out.f32(w);
+        out.f32(h);
+        out.u64(self.time_limit);
+        out.u64(self.rem_time);
+        out.u8(self.rem_lives);
+        out.u8(self.rem_tanks);
+        out.u32(self.kills);
+        out.u32(self.score);
+        out.u8(self.lives);
+        write_vec2(&mut out, self.player_spawn);
+        out.u32(self.tank_spawns.len() as u32);
+        for spawn in &self.tank_spawns {
+            out.u32(spawn.x as u32);
+            out.u32(spawn.y as u32);
+            write_direction(&mut out, spawn.direction);
+            write_controller(&mut out, &spawn.controller);
+        }
+        out.u32(self.hatch.len() as u32);
+        for &at in &self.hatch {
+            out.u64(at);
+        }
+        out.u32(self.spawn_points.len() as u32);
+        for &(x, y) in &self.spawn_points {
+            out.u32(x as u32);
+            out.u32(y as u32);
+        }
+        out.u32(self.waves.len() as u32);
+        for wave in &self.waves {
+            out.u64(wave.time);
+            out.u32(wave.tanks.len() as u32);
+            for controller in &wave.tanks {
+                write_controller(&mut out, controller);
+            }
+        }
+        out.u32(self.wave_queue.len() as u32);
+        for controller in &self.wave_queue {
+            write_controller(&mut out, controller);
+        }
+
+        let grid = &self.static_block_types;
+        out.u32(grid.get_width() as u32);
+        out.u32(grid.get_height() as u32);
+        grid.for_each(|_, _, &block_type| write_block(&mut out, block_type));
+        self.terrain
+            .for_each(|_, _, &terrain| write_terrain(&mut out, terrain));
+        out.u32(self.id_counter);
+
+        out.u32(self.sliding_block_ids.len() as u32);
+        for index in 0..self.sliding_block_ids.len() {
+            out.u32(self.sliding_block_ids[index]);
+            write_vec2(&mut out, self.sliding_block_positions[index]);
+            write_direction(&mut out, self.sliding_block_directions[index]);
+            write_block(&mut out, Some(self.sliding_block_types[index]));
+        }
+
+        out.u32(self.projectile_ids.len() as u32);
+        for index in 0..self.projectile_ids.len() {
+            out.u32(self.projectile_ids[index]);
+            write_vec2(&mut out, self.projectile_positions[index]);
+            write_direction(&mut out, self.projectile_directions[index]);
+            out.u32(self.projectile_owners[index]);
+            out.f32(self.projectile_speeds[index]);
+        }
+
+        out.u32(self.tank_ids.len() as u32);
+        for index in 0..self.tank_ids.len() {
+            out.u32(self.tank_ids[index]);
+            write_vec2(&mut out, self.tank_positions[index]);
+            write_direction(&mut out, self.tank_directions[index]);
+            write_tank_state(&mut out, self.tank_states[index]);
+            write_controller(&mut out, &self.tank_controllers[index]);
+            out.u64(self.tank_shields[index]);
+        }
+
+        out.u32(self.tank_schedule.len() as u32);
+        for &(at, (id, state)) in &self.tank_schedule {
+            out.u64(at);
+            out.u32(id);
+            write_tank_state(&mut out, state);
+        }
+
+        out.u64(self.rng);
+
+        out.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader(bytes);
+        reader.header(MAGIC, VERSION, "snapshot")?;
+        let r = &mut reader;
+
+        let clock = r.u64()?;
+        let game_state = match r.u8()? {
+            0 => GameState::Playing,
+            1 => GameState::GameOver,
+            2 => GameState::Won,
+            n => return Err(invalid(format!("unknown game state {}", n))),
+        };
+        let camera = Vec4(r.f32()?, r.f32()?, r.f32()?, r.f32()?);
+        let time_limit = r.u64()?;
+        let rem_time = r.u64()?;
+        let rem_lives = r.u8()?;
+        let rem_tanks = r.u8()?;
+        let kills = r.u32()?;
+        let score = r.u32()?;
+        let lives = r.u8()?;
+        let player_spawn = read_vec2(r)?;
+        let mut tank_spawns = vec![];
+        for _ in 0..r.u32()? {
+            tank_spawns.push(TankSpawn {
+                x: r.u32()? as usize,
+                y: r.u32()? as usize,
+                direction: read_direction(r)?,
+                controller: read_controller(r)?,
+            });
+        }
+        let mut hatch = vec![];
+        for _ in 0..r.u32()? {
+            hatch.push(r.u64()?);
+        }
+        let mut spawn_points = vec![];
+        for _ in 0..r.u32()? {
+            spawn_points.push((r.u32()? as usize, r.u32()? as usize));
+        }
+        let mut waves = vec![];
+        for _ in 0..r.u32()? {
+            let time = r.u64()?;
+            let mut tanks = vec![];
+            for _ in 0..r.u32()? {
+                tanks.push(read_controller(r)?);
+            }
+            waves.push(Wave { time, tanks });
+        }
+        let mut wave_queue = vec![];
+        for _ in 0..r.u32()? {
+            wave_queue.push(read_controller(r)?);
+        }
+
+        // every cell takes at least a byte, so a map bigger than what's left is garbage
+        let width = r.u32()? as usize;
+        let height = r.u32()? as usize;
+        let size = match width.checked_mul(height) {
+            Some(size) if size <= r.0.len() => size,
+            _ => return Err(invalid(format!("a {}x{} map is too big", width, height))),
+        };
+        let mut cells = Vec::with_capacity(size);
+        for _ in 0..size {
+            cells.push(read_block(r)?);
+        }
+        let static_block_types = Array2D::from_box(width, height, cells.into_boxed_slice());
+        let mut ground = Vec::with_capacity(size);
+        for _ in 0..size {
+            ground.push(read_terrain(r)?);
+        }
+        let terrain = Array2D::from_box(width, height, ground.into_boxed_slice());
+        let id_counter = r.u32()?;
+
+        let mut sliding_block_ids = vec![];
+        let mut sliding_block_positions = vec![];
+        let mut sliding_block_directions = vec![];
+        let mut sliding_block_types = vec![];
+        for _ in 0..r.u32()? {
+            sliding_block_ids.push(r.u32()?);
+            sliding_block_positions.push(read_vec2(r)?);
+            sliding_block_directions.push(read_direction(r)?);
+            match read_block(r)? {
+                Some(block_type) => sliding_block_types.push(block_type),
+                None => return Err(invalid("sliding block without a type")),
+            }
+        }
+
+        let mut projectile_ids = vec![];
+        let mut projectile_positions = vec![];
+        let mut projectile_directions = vec![];
+        let mut projectile_owners = vec![];
+        let mut projectile_speeds = vec![];
+        for _ in 0..r.u32()? {
+            projectile_ids.push(r.u32()?);
+            projectile_positions.push(read_vec2(r)?);
+            projectile_directions.push(read_direction(r)?);
+            projectile_owners.push(r.u32()?);
+            projectile_speeds.push(r.f32()?);
+        }
+
+        let mut tank_ids = vec![];
+        let mut tank_positions = vec![];
+        let mut tank_directions = vec![];
+        let mut tank_states = vec![];
+        let mut tank_controllers = vec![];
+        let mut tank_shields = vec![];
+        for _ in 0..r.u32()? {
+            tank_ids.push(r.u32()?);
+            tank_positions.push(read_vec2(r)?);
+            tank_directions.push(read_direction(r)?);
+            tank_states.push(read_tank_state(r)?);
+            tank_controllers.push(read_controller(r)?);
+            tank_shields.push(r.u64()?);
+        }
+
+        let mut tank_schedule = vec![];
+        for _ in 0..r.u32()? {
+            let at = r.u64()?;
+            let id = r.u32()?;
+            tank_schedule.push((at, (id, read_tank_state(r)?)));
+        }
+
+        let rng = r.u64()?;
+
+        Ok(Self {
+            clock,
+            game_state,
+            camera,
+            time_limit,
+            rem_time,
+            rem_lives,
+            rem_tanks,
+            kills,
+            score,
+            lives,
+            player_spawn,
+            tank_spawns,
+            hatch,
+            spawn_points,
+            waves,
+            wave_queue,
+            static_block_types,
+            terrain,
+            id_counter,
+            sliding_block_ids,
+            sliding_block_positions,
+            sliding_block_directions,
+            sliding_block_types,
+            projectile_ids,
+            projectile_positions,
+            projectile_directions,
+            projectile_owners,
+            projectile_speeds,
+            tank_ids,
+            tank_positions,
+            tank_directions,
+            tank_states,
+            tank_controllers,
+            tank_shields,
+            tank_schedule,
+            rng,
+        })
+    }
+}
+
+fn write_vec2(out: &mut Writer, Vec2(x, y): Vec2f) {
+    out.f32(x);
+    out.f32(y);
+}
+
+fn read_vec2(r: &mut Reader) -> Result<Vec2f> {
+    Ok(Vec2(r.f32()?, r.f32()?))
+}
+
+fn write_direction(out: &mut Writer, direction: Direction) {
+    out.u8(match direction {
+        Direction::Up => 0,
+        Direction::Down => 1,
+        Direction::Left => 2,
+        Direction::Right => 3,
+    });
+}
+
+fn read_direction(r: &mut Reader) -> Result<Direction> {
+    match r.u8()? {
+        n if (n as usize) < Direction::ALL.len() => Ok(Direction::ALL[n as usize]),
+        n => Err(invalid(format!("unknown direction {}", n))),
+    }
+}
+
+// blocks use their level file characters
+fn write_block(out: &mut Writer, block_type: Option<BlockType>) {
+    out.u8(crate::level::block_to_char(block_type) as u8);
+}
+
+fn read_block(r: &mut Reader) -> Result<Option<BlockType>> {
+    let c = r.u8()? as char;
+    crate::level::char_to_block(c).ok_or_else(|| invalid(format!("unknown block '{}'", c)))
+}
+
+// and so does terrain
+fn write_terrain(out: &mut Writer, terrain: Terrain) {
+    out.u8(crate::level::terrain_to_char(terrain) as u8);
+}
+
+fn read_terrain(r: &mut Reader) -> Result<Terrain> {
+    let c = r.u8()? as char;
+    crate::level::char_to_terrain(c).ok_or_else(|| invalid(format!("unknown terrain '{}'", c)))
+}
+
+fn write_tank_state(out: &mut Writer, state: TankState) {
+    match state {
+        TankState::Idle => out.u8(0),
+        TankState::Moving { timer, start, slid } => {
+            out.u8(1);
+            out.u64(timer.start);
+            out.u64(timer.duration);
+            write_vec2(out, start);
+            out.u8(slid);
+        }
+        TankState::Delayed => out.u8(2),
+        TankState::Exploding => out.u8(3),
+        TankState::Dead => out.u8(4),
+        TankState::Stunned => out.u8(5),
+    }
+}
+
+fn read_tank_state(r: &mut Reader) -> Result<TankState> {
+    match r.u8()? {
+        0 => Ok(TankState::Idle),
+        1 => Ok(TankState::Moving {
+            timer: Timer::new(r.u64()?, r.u64()?),
+            start: read_vec2(r)?,
+            slid: r.u8()?,
+        }),
+        2 => Ok(TankState::Delayed),
+        3 => Ok(TankState::Exploding),
+        4 => Ok(TankState::Dead),
+        5 => Ok(TankState::Stunned),
+        n => Err(invalid(format!("unknown tank state {}", n))),
+    }
+}
+
+fn write_controller(out: &mut Writer, controller: &Controller) {
+    match controller {
+        Controller::Player => out.u8(0),
+        Controller::Wander => out.u8(1),
+        Controller::Patrol { route, step } => {
+            out.u8(2);
+            out.u32(route.len() as u32);
+            for &direction in route {
+                write_direction(out, direction);
+            }
+            out.u32(*step as u32);
+        }
+        Controller::Chase => out.u8(3),
+    }
+}
+
+fn read_controller(r: &mut Reader) -> Result<Controller> {
+    match r.u8()? {
+        0 => Ok(Controller::Player),
+        1 => Ok(Controller::Wander),
+        2 => {
+            let mut route = vec![];
+            for _ in 0..r.u32()? {
+                route.push(read_direction(r)?);
+            }
+            let step = r.u32()? as usize;
+            Ok(Controller::Patrol { route, step })
+        }
+        3 => Ok(Controller::Chase),
+        n => Err(invalid(format!("unknown controller {}", n))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::event::{Event, Key};
+    use super::super::update::State;
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let level = crate::level::Level::parse(
+            "hatch 1\n\
+             tank 1 1 down player\n\
+             tank 5 3 left patrol ud\n\
+             slide 1 4 right *\n\
+             spawn 6 1\n\
+             wave 0 wander chase\n\
+             map 8 6\n\
+             ########\n\
+             #.o....#\n\
+             #..a...#\n\
+             #.@....#\n\
+             #......#\n\
+             ########\n\
+             terrain\n\
+             ........\n\
+             ......-.\n\
+             ........\n\
+             ...~....\n\
+             ....T...\n\
+             ........\n",
+        )
+        .unwrap();
+
+        // play a little, so there's something in flight
+        let mut state = State::from_level(&level);
+        state.pre_step(Some(Event::KeyDown(Key::X)).into_iter());
+        for _ in 0..5 {
+            state.step();
+        }
+
+        let bytes = state.snapshot().to_bytes();
+        let read = Snapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(read.to_bytes(), bytes);
+        assert!(!read.sliding_block_ids.is_empty());
+        assert!(!read.projectile_ids.is_empty());
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn bad_input() {
+        let level = crate::level::Level::parse(
+            "tank 1 1 down player\n\
+             tank 3 2 left wander\n\
+             map 5 4\n\
+             #####\n\
+             #.o.#\n\
+             #...#\n\
+             #####\n",
+        )
+        .unwrap();
+        let bytes = State::from_level(&level).snapshot().to_bytes();
+
+        // cut short anywhere, it's an error
+        for len in 0..bytes.len() {
+            assert!(Snapshot::from_bytes(&bytes[..len]).is_err());
+        }
+
+        // and any one byte gone bad gives either an error or a snapshot, never a panic
+        for index in 0..bytes.len() {
+            let mut bad = bytes.clone();
+            bad[index] = 0xff;
+            let _ = Snapshot::from_bytes(&bad);
+        }
+    }
+}
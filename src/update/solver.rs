@@ -0,0 +1,592 @@
+// An offline solver for levels, treated as puzzles. The player moves in 8px steps and
+// kicks the block in front of it, and the level is won once every other tank has been
+// crushed and every egg smashed. Enemies are taken to sit still on their spawns, since
+// what the AI will do can't be known ahead of time, eggs never hatch, waves never come,
+// ice doesn't carry the player on, and blocks finish sliding before the player acts again.
+// Moves and pushes go through the same functions the game uses.
+//
+// The player never fires. Shots can kill enemies in the game, but the search only ever
+// pushes, so a level it can't find a win for may still be beaten by shooting.
+//
+// Only pushes change the puzzle, so a search state is the grid (and the terrain, since
+// blocks fill in water), the enemies left, and
+// the area the player can drive around in, named by its top left-most spot. Time bonuses
+// in that area count as picked up.
+//
+// Open levels have far too many states to search them all, so once the quickest win is
+// found the search only carries on DEAD_END_HORIZON pushes further. Dead ends are then
+// only the states it could see all the way to the bottom of without finding a win.
+
+use super::func::*;
+use super::misc::*;
+use super::path::find_path;
+use super::timer::Scheduler;
+use crate::array2d::*;
+use crate::common::*;
+use crate::level::{
+    block_to_char, char_to_block, char_to_terrain, direction_name, terrain_to_char, Level,
+};
+use std::collections::{HashMap, VecDeque};
+
+// searches stop here, rather than eat all the memory on a wide open level
+pub const MAX_STATES: usize = 50_000;
+
+// how many pushes past the quickest win to keep looking for dead ends
+pub const DEAD_END_HORIZON: usize = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Move(Direction),
+    Push(Direction),
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Action::Move(dir) => write!(f, "{}", direction_name(*dir)),
+            Action::Push(dir) => write!(f, "push {}", direction_name(*dir)),
+        }
+    }
+}
+
+pub struct Report {
+    pub states: usize,
+    // false if the search stopped before it had seen every state
+    pub complete: bool,
+    // the most pushes deep every state was looked at
+    pub depth: usize,
+    // the way to win with the fewest pushes, and the moves between them
+    pub solution: Option<Vec<Action>>,
+    // states no pushes win from (as far as the search went), and the fewest
+    // pushes into one of them
+    pub dead_ends: usize,
+    pub dead_end_example: Option<Vec<Action>>,
+}
+
+impl Report {
+    // whether the level can be shipped
+    pub fn ok(&self) -> bool {
+        self.solution.is_some()
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let actions = |actions: &[Action]| {
+            let names: Vec<_> = actions.iter().map(|action| action.to_string()).collect();
+            names.join(", ")
+        };
+
+        match (&self.solution, self.complete) {
+            (Some(solution), _) => {
+                let pushes = solution
+                    .iter()
+                    .filter(|action| match action {
+                        Action::Push(_) => true,
+                        _ => false,
+                    })
+                    .count();
+                writeln!(
+                    f,
+                    "solvable in {} pushes (the fewest), taking {} actions",
+                    pushes,
+                    solution.len()
+                )?;
+                writeln!(f, "  solution: {}", actions(solution))?;
+            }
+            (None, true) => writeln!(f, "no push-only solution")?,
+            (None, false) => writeln!(f, "unknown, gave up after {} states", self.states)?,
+        }
+
+        writeln!(f, "  states: {}", self.states)?;
+        match self.complete {
+            true => writeln!(f, "  dead ends: {}", self.dead_ends)?,
+            false => writeln!(
+                f,
+                "  dead ends: {}, looking {} pushes deep",
+                self.dead_ends, self.depth
+            )?,
+        }
+        match &self.dead_end_example {
+            Some(example) if example.is_empty() => writeln!(f, "  e.g. from the start")?,
+            Some(example) => writeln!(f, "  e.g. after: {}", actions(example))?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+// a node in the search. the grid is kept in its level file characters so states hash cheaply
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Node {
+    player: (usize, usize), // in 8px steps, the first spot in the player's area
+    grid: Vec<u8>,
+    terrain: Vec<u8>,
+    alive: u64, // one bit per enemy
+}
+
+impl Node {
+    fn won(&self) -> bool {
+        let egg = block_to_char(Some(BlockType::Egg)) as u8;
+        self.alive == 0 && !self.grid.contains(&egg)
+    }
+}
+
+pub fn solve(level: &Level) -> Report {
+    search(level, false)
+}
+
+// whether the level can be won by pushing alone, stopping at the first win found
+// without looking for dead ends
+pub fn solvable(level: &Level) -> bool {
+    search(level, true).ok()
+}
+
+fn search(level: &Level, stop_at_win: bool) -> Report {
+    let width = level.static_block_types.get_width();
+    let height = level.static_block_types.get_height();
+
+    let player = level.tanks.iter().find(|tank| match tank.controller {
+        Controller::Player => true,
+        _ => false,
+    });
+    let enemies: Vec<Vec2f> = level
+        .tanks
+        .iter()
+        .filter(|tank| match tank.controller {
+            Controller::Player => false,
+            _ => true,
+        })
+        .map(|tank| Vec2((tank.x * 16) as f32, (tank.y * 16) as f32))
+        .take(64)
+        .collect();
+    let player = match player {
+        Some(tank) => Vec2((tank.x * 16) as f32, (tank.y * 16) as f32),
+        None => return no_solution(0),
+    };
+
+    // let anything already sliding come to rest first
+    let mut grid = level.static_block_types.clone();
+    let mut terrain = level.terrain.clone();
+    let mut alive = match enemies.len() {
+        64 => !0,
+        n => (1u64 << n) - 1,
+    };
+    let sliding: Vec<_> = level
+        .sliding_blocks
+        .iter()
+        .map(|block| {
+            let pos = Vec2((block.x * 16) as f32, (block.y * 16) as f32);
+            (pos, block.direction, block.block_type)
+        })
+        .collect();
+    match settle(&mut grid, &mut terrain, sliding, player, &enemies, alive) {
+        Some(still_alive) => alive = still_alive,
+        None => return no_solution(0),
+    }
+    let start = node(grid, &terrain, player, alive);
+
+    // breadth first, remembering how each state was first reached and where it leads
+    let mut index = HashMap::new();
+    let mut nodes = vec![start.clone()];
+    let mut parents: Vec<Option<(usize, Push)>> = vec![None];
+    let mut depths = vec![0];
+    let mut children: Vec<Vec<usize>> = vec![];
+    index.insert(start, 0);
+
+    // states left with pushes that were never looked at
+    let mut open = vec![false];
+    let mut limit = usize::MAX;
+
+    let mut queue = VecDeque::new();
+    queue.push_back(0);
+    while let Some(current) = queue.pop_front() {
+        while children.len() <= current {
+            children.push(vec![]);
+        }
+
+        // won states are where the search ends
+        if nodes[current].won() {
+            if stop_at_win {
+                break;
+            }
+            continue;
+        }
+
+        // past the quickest win, only look far enough ahead to find dead ends
+        if depths[current] >= limit {
+            open[current] = true;
+            continue;
+        }
+
+        for (push, next) in expand(&nodes[current], width, height, &enemies) {
+            let next_index = match index.get(&next) {
+                Some(&next_index) => next_index,
+                None => {
+                    if nodes.len() == MAX_STATES {
+                        open[current] = true;
+                        continue;
+                    }
+                    if next.won() && limit == usize::MAX {
+                        limit = depths[current] + 1 + DEAD_END_HORIZON;
+                    }
+                    let next_index = nodes.len();
+                    index.insert(next.clone(), next_index);
+                    nodes.push(next);
+                    parents.push(Some((current, push)));
+                    depths.push(depths[current] + 1);
+                    open.push(false);
+                    queue.push_back(next_index);
+                    next_index
+                }
+            };
+            children[current].push(next_index);
+        }
+    }
+    children.resize(nodes.len(), vec![]);
+    for &unseen in &queue {
+        open[unseen] = true;
+    }
+    let complete = !open.contains(&true);
+
+    // states are numbered in the order they were found, so the first won one is the closest
+    let solution = (0..nodes.len())
+        .find(|&i| nodes[i].won())
+        .map(|i| actions_to(&nodes, &parents, i, player, width, height));
+
+    // a dead end can't lead to a won state, and is only known to be one if nothing it
+    // leads to was left open
+    let winnable = leads_to(&children, nodes.iter().map(Node::won).collect());
+    let undecided = leads_to(&children, open.clone());
+    let dead: Vec<_> = (0..nodes.len())
+        .filter(|&i| !winnable[i] && !undecided[i])
+        .collect();
+    let dead_end_example = dead
+        .first()
+        .map(|&i| actions_to(&nodes, &parents, i, player, width, height));
+
+    // every state shallower than the first one left open was looked at in full
+    let depth = (0..nodes.len())
+        .filter(|&i| open[i])
+        .map(|i| depths[i])
+        .min()
+        .unwrap_or_else(|| depths.iter().copied().max().unwrap_or(0));
+
+    Report {
+        states: nodes.len(),
+        complete,
+        depth,
+        solution,
+        dead_ends: dead.len(),
+        dead_end_example,
+    }
+}
+
+fn no_solution(states: usize) -> Report {
+    Report {
+        states,
+        complete: true,
+        depth: 0,
+        solution: None,
+        dead_ends: 0,
+        dead_end_example: None,
+    }
+}
+
+fn encode(grid: &Array2D<Option<BlockType>>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(grid.get_width() * grid.get_height());
+    grid.for_each(|_, _, &block_type| out.push(block_to_char(block_type) as u8));
+    out
+}
+
+fn decode(width: usize, height: usize, grid: &[u8]) -> Array2D<Option<BlockType>> {
+    Array2D::from_closure(width, height, |i, j| {
+        char_to_block(grid[i + j * width] as char).unwrap_or(None)
+    })
+}
+
+fn encode_terrain(terrain: &Array2D<Terrain>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(terrain.get_width() * terrain.get_height());
+    terrain.for_each(|_, _, &terrain| out.push(terrain_to_char(terrain) as u8));
+    out
+}
+
+fn decode_terrain(width: usize, height: usize, terrain: &[u8]) -> Array2D<Terrain> {
+    Array2D::from_closure(width, height, |i, j| {
+        char_to_terrain(terrain[i + j * width] as char).unwrap_or(Terrain::Floor)
+    })
+}
+
+// where the player stood and which way it pushed
+#[derive(Copy, Clone)]
+struct Push {
+    from: (usize, usize),
+    dir: Direction,
+}
+
+fn node(
+    mut grid: Array2D<Option<BlockType>>,
+    terrain: &Array2D<Terrain>,
+    player: Vec2f,
+    alive: u64,
+) -> Node {
+    let area = area(&mut grid, terrain, player);
+    Node {
+        player: area.into_iter().min_by_key(|&(i, j)| (j, i)).unwrap(),
+        grid: encode(&grid),
+        terrain: encode_terrain(terrain),
+        alive,
+    }
+}
+
+// every spot the player can drive to, picking up time bonuses on the way
+fn area(
+    grid: &mut Array2D<Option<BlockType>>,
+    terrain: &Array2D<Terrain>,
+    player: Vec2f,
+) -> Vec<(usize, usize)> {
+    let (width, height) = (grid.get_width(), grid.get_height());
+    let start = ((player.0 / 8.) as usize, (player.1 / 8.) as usize);
+    let mut seen = Array2D::from_closure(width * 2, height * 2, |i, j| (i, j) == start);
+    let mut spots = vec![start];
+
+    let mut next_spot = 0;
+    while next_spot < spots.len() {
+        let (i, j) = spots[next_spot];
+        next_spot += 1;
+
+        let pos = Vec2((i * 8) as f32, (j * 8) as f32);
+        time_bonus_pickup(1, &[pos], &[Controller::Player], grid);
+
+        for &dir in Direction::ALL.iter() {
+            if !front_on_map(pos, dir, width, height) || !tank_can_move(pos, dir, grid, terrain) {
+                continue;
+            }
+            let Vec2(dx, dy) = dir.vec2i();
+            let next = ((i as i32 + dx) as usize, (j as i32 + dy) as usize);
+            if !seen[next] {
+                seen[next] = true;
+                spots.push(next);
+            }
+        }
+    }
+
+    spots
+}
+
+// every push the player can make from a state, and where each one ends up
+fn expand(node: &Node, width: usize, height: usize, enemies: &[Vec2f]) -> Vec<(Push, Node)> {
+    let mut grid = decode(width, height, &node.grid);
+    let terrain = decode_terrain(width, height, &node.terrain);
+    let start = Vec2((node.player.0 * 8) as f32, (node.player.1 * 8) as f32);
+    let mut spots = area(&mut grid, &terrain, start);
+    spots.sort();
+
+    // a block goes the same way whichever spot it's pushed from, so each block and
+    // direction only needs settling once
+    let mut pushed = vec![];
+
+    let mut out = vec![];
+    for from in spots {
+        let pos = Vec2((from.0 * 8) as f32, (from.1 * 8) as f32);
+        for &dir in Direction::ALL.iter() {
+            // the cells in front have to be on the map, and most of the time they're empty
+            if !front_on_map(pos, dir, width, height) || front_empty(&grid, pos, dir) {
+                continue;
+            }
+
+            let mut grid = grid.clone();
+            let mut terrain = terrain.clone();
+            let mut sliding_block_ids = vec![];
+            let mut sliding_block_positions = vec![];
+            let mut sliding_block_directions = vec![];
+            let mut sliding_block_types = vec![];
+            let (_, smashed) = tank_push_command(
+                std::iter::once(0),
+                0,
+                &mut grid,
+                &terrain,
+                &[0],
+                &[pos],
+                &[dir],
+                &mut [TankState::Idle],
+                &mut Scheduler::new(),
+                &mut 0,
+                &mut sliding_block_ids,
+                &mut sliding_block_positions,
+                &mut sliding_block_directions,
+                &mut sliding_block_types,
+            );
+            // smashing an egg, or chipping a brick with a block that can't move
+            let chipped = sliding_block_ids.is_empty() && encode(&grid) != node.grid;
+            if !smashed.is_empty() || chipped {
+                out.push((
+                    Push { from, dir },
+                    self::node(grid, &terrain, pos, node.alive),
+                ));
+                continue;
+            }
+            if sliding_block_ids.is_empty() {
+                continue;
+            }
+            let Vec2(bx, by) = sliding_block_positions[0];
+            if pushed.contains(&(bx as usize, by as usize, dir)) {
+                continue;
+            }
+            pushed.push((bx as usize, by as usize, dir));
+
+            let sliding = vec![(
+                sliding_block_positions[0],
+                sliding_block_directions[0],
+                sliding_block_types[0],
+            )];
+            if let Some(alive) = settle(&mut grid, &mut terrain, sliding, pos, enemies, node.alive)
+            {
+                out.push((Push { from, dir }, self::node(grid, &terrain, pos, alive)));
+            }
+        }
+    }
+
+    out
+}
+
+// runs sliding blocks until they've all come to rest, returning which enemies survived,
+// or None if the player got crushed
+fn settle(
+    grid: &mut Array2D<Option<BlockType>>,
+    terrain: &mut Array2D<Terrain>,
+    sliding: Vec<(Vec2f, Direction, BlockType)>,
+    player: Vec2f,
+    enemies: &[Vec2f],
+    alive: u64,
+) -> Option<u64> {
+    let mut sliding_block_ids: Vec<u32> = (0..sliding.len() as u32).collect();
+    let mut sliding_block_positions: Vec<_> = sliding.iter().map(|s| s.0).collect();
+    let mut sliding_block_directions: Vec<_> = sliding.iter().map(|s| s.1).collect();
+    let mut sliding_block_types: Vec<_> = sliding.iter().map(|s| s.2).collect();
+
+    // the player first, then every enemy still around
+    let mut tank_positions = vec![player];
+    tank_positions.extend(enemies.iter().copied());
+    let tank_ids: Vec<u32> = (0..tank_positions.len() as u32).collect();
+    let mut tank_states: Vec<_> = (0..tank_positions.len())
+        .map(|index| match index {
+            0 => TankState::Idle,
+            _ if alive & (1 << (index - 1)) != 0 => TankState::Idle,
+            _ => TankState::Dead,
+        })
+        .collect();
+    let tank_controllers: Vec<_> = (0..tank_positions.len())
+        .map(|index| match index {
+            0 => Controller::Player,
+            _ => Controller::Wander,
+        })
+        .collect();
+    let tank_shields = vec![0; tank_positions.len()];
+    let mut tank_schedule = Scheduler::new();
+
+    while !sliding_block_ids.is_empty() {
+        sliding_block_movement(
+            grid,
+            terrain,
+            &mut sliding_block_ids,
+            &mut sliding_block_positions,
+            &mut sliding_block_directions,
+            &mut sliding_block_types,
+        );
+        sliding_block_tank_collision(
+            sliding_block_ids.len(),
+            tank_ids.len(),
+            0,
+            &sliding_block_positions,
+            &tank_ids,
+            &tank_positions,
+            &tank_controllers,
+            &mut tank_states,
+            &tank_shields,
+            &mut tank_schedule,
+        );
+    }
+
+    if let TankState::Exploding = tank_states[0] {
+        return None;
+    }
+    let mut alive = 0;
+    for (index, state) in tank_states.iter().enumerate().skip(1) {
+        if let TankState::Idle = state {
+            alive |= 1 << (index - 1);
+        }
+    }
+    Some(alive)
+}
+
+// the two points tank_can_move and tank_push_command look at
+fn front_on_map(pos: Vec2f, dir: Direction, width: usize, height: usize) -> bool {
+    let udir = dir.vec2f();
+    let utan = Vec2(udir.1, udir.0);
+    let tar = pos + Vec2(8., 8.) + udir * 9.;
+    let (w, h) = ((width * 16) as f32, (height * 16) as f32);
+    [tar + utan, tar - utan]
+        .iter()
+        .all(|&Vec2(x, y)| x >= 0. && y >= 0. && x < w && y < h)
+}
+
+fn front_empty(grid: &Array2D<Option<BlockType>>, pos: Vec2f, dir: Direction) -> bool {
+    let udir = dir.vec2f();
+    let utan = Vec2(udir.1, udir.0);
+    let tar = pos + Vec2(8., 8.) + udir * 9.;
+    [tar + utan, tar - utan]
+        .iter()
+        .all(|&Vec2(x, y)| grid[(x as usize / 16, y as usize / 16)].is_none())
+}
+
+// the pushes leading to a state, with the drives in between filled back in
+fn actions_to(
+    nodes: &[Node],
+    parents: &[Option<(usize, Push)>],
+    mut index: usize,
+    player: Vec2f,
+    width: usize,
+    height: usize,
+) -> Vec<Action> {
+    let mut pushes = vec![];
+    while let Some((parent, push)) = parents[index] {
+        pushes.push((parent, push));
+        index = parent;
+    }
+    pushes.reverse();
+
+    let mut actions = vec![];
+    let mut pos = player;
+    for (parent, push) in pushes {
+        let grid = decode(width, height, &nodes[parent].grid);
+        let terrain = decode_terrain(width, height, &nodes[parent].terrain);
+        let to = Vec2((push.from.0 * 8) as f32, (push.from.1 * 8) as f32);
+        let path = find_path(&grid, &terrain, pos, to).unwrap_or_default();
+        actions.extend(path.into_iter().map(Action::Move));
+        actions.push(Action::Push(push.dir));
+        pos = to;
+    }
+    actions
+}
+
+// walks the search graph backwards, finding every state that leads to one of the marked ones
+fn leads_to(children: &[Vec<usize>], mut marked: Vec<bool>) -> Vec<bool> {
+    let mut parents_of = vec![vec![]; children.len()];
+    for (parent, next) in children.iter().enumerate() {
+        for &child in next {
+            parents_of[child].push(parent);
+        }
+    }
+
+    let mut queue: VecDeque<usize> = (0..marked.len()).filter(|&i| marked[i]).collect();
+    while let Some(current) = queue.pop_front() {
+        for &parent in &parents_of[current] {
+            if !marked[parent] {
+                marked[parent] = true;
+                queue.push_back(parent);
+            }
+        }
+    }
+    marked
+}
@@ -0,0 +1,72 @@
+// all times are microseconds of simulation time, which stands still while the game is paused
+
+#[derive(Copy, Clone, Debug)]
+pub struct Timer {
+    pub start: u64,
+    pub duration: u64,
+}
+
+impl Timer {
+    pub fn new(now: u64, duration: u64) -> Self {
+        Self {
+            start: now,
+            duration,
+        }
+    }
+
+    pub fn elapsed(self, now: u64) -> u64 {
+        now.saturating_sub(self.start)
+    }
+
+    // how far along the timer is, from 0 to 1
+    pub fn ratio(self, now: u64) -> f32 {
+        match self.duration {
+            0 => 1.,
+            duration => (self.elapsed(now) as f64 / duration as f64).min(1.) as f32,
+        }
+    }
+
+    pub fn done(self, now: u64) -> bool {
+        self.elapsed(now) >= self.duration
+    }
+}
+
+// a queue of things to happen at set times, kept in the order they will fire
+pub struct Scheduler<T> {
+    entries: Vec<(u64, T)>,
+}
+
+impl<T> Scheduler<T> {
+    pub fn new() -> Self {
+        Self { entries: vec![] }
+    }
+
+    // anything scheduled for the same time fires in the order it was scheduled
+    pub fn schedule(&mut self, at: u64, t: T) {
+        let index = self
+            .entries
+            .iter()
+            .position(|&(entry_at, _)| entry_at > at)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(index, (at, t));
+    }
+
+    pub fn retain(&mut self, mut func: impl FnMut(&T) -> bool) {
+        self.entries.retain(|(_, t)| func(t));
+    }
+
+    // removes and returns everything due at or before now
+    pub fn due(&mut self, now: u64) -> impl Iterator<Item = T> {
+        let count = self
+            .entries
+            .iter()
+            .take_while(|&&(at, _)| at <= now)
+            .count();
+        let due: Vec<_> = self.entries.drain(..count).map(|(_, t)| t).collect();
+        due.into_iter()
+    }
+
+    pub fn entries(&self) -> &[(u64, T)] {
+        &self.entries
+    }
+}
@@ -1,33 +1,87 @@
+use super::editor::*;
 use super::event::Event;
 use super::func::*;
+use super::history::History;
 use super::misc::*;
+use super::snapshot::Snapshot;
+use super::timer::*;
+use super::US_PER_TICK;
 use crate::array2d::*;
 use crate::common::*;
+use crate::level::{
+    block_to_char, direction_name, on_border, terrain_to_char, Level, SlidingBlockSpawn, TankSpawn,
+    Wave, MAX_TIME,
+};
 use crate::render::render_state::RenderState;
 
 pub(super) struct State {
     exit: bool,
 
+    // the next tick to be simulated, and the inputs waiting on it
+    tick: u64,
+    events: Vec<(u64, Event)>,
+
+    // simulation time, which only runs while unpaused
+    paused: bool,
+    clock: u64, // in microseconds
+
     // some keyboard stuff
     upkey_down: bool,
     downkey_down: bool,
     leftkey_down: bool,
     rightkey_down: bool,
     zkey_down: bool,
+    xkey_down: bool,
 
     upkey_was_down: bool,
     downkey_was_down: bool,
     leftkey_was_down: bool,
     rightkey_was_down: bool,
     zkey_was_down: bool,
+    xkey_was_down: bool,
+
+    // held to rewind
+    rkey_down: bool,
+
+    // these only act on the first press, not on key repeat
+    pkey_down: bool,
+    ekey_down: bool,
+    ukey_down: bool,
+    f5key_down: bool,
+    f9key_down: bool,
+
+    // and some mouse stuff, in window pixels
+    window_size: Vec2f,
+    cursor: Vec2f,
+    lmb_down: bool,
+    rmb_down: bool,
+    lmb_was_down: bool,
+
+    // the level editor, while it's open
+    editor: Option<Editor>,
 
     // various game properties
+    game_state: GameState,
     camera: Vec4f,
-    rem_time: u64, // in microseconds
-    rem_tanks: u8,
+    time_limit: u64, // in microseconds
+    rem_time: u64,   // in microseconds
+    rem_lives: u8,   // lives left after the current one
+    rem_tanks: u8,   // enemies still to beat, for the hud
+    kills: u32,
+    score: u32,
+    lives: u8, // what the level started with
+    player_spawn: Vec2f,
+    tank_spawns: Vec<TankSpawn>,
+    hatch: Vec<u64>, // in microseconds, when an egg breaks open
 
-    // static blocks
+    // where wave tanks come in, when (in microseconds), and those still waiting on a clear spot
+    spawn_points: Vec<(usize, usize)>,
+    waves: Vec<Wave>,
+    wave_queue: Vec<Controller>,
+
+    // static blocks, and the ground under them
     static_block_types: Array2D<Option<BlockType>>,
+    terrain: Array2D<Terrain>,
 
     // id counter
     id_counter: u32,
@@ -38,131 +92,324 @@ pub(super) struct State {
     sliding_block_directions: Vec<Direction>,
     sliding_block_types: Vec<BlockType>,
 
+    // projectiles
+    projectile_ids: Vec<u32>,
+    projectile_positions: Vec<Vec2f>, // the middle of the shot
+    projectile_directions: Vec<Direction>,
+    projectile_owners: Vec<u32>, // the tank that fired it
+    projectile_speeds: Vec<f32>, // pixels per tick
+
     // tanks
     tank_ids: Vec<u32>,
     tank_positions: Vec<Vec2f>,
     tank_directions: Vec<Direction>,
     tank_states: Vec<TankState>,
+    tank_controllers: Vec<Controller>,
+    tank_shields: Vec<u64>, // invulnerable until this time, in microseconds
+    tank_schedule: Scheduler<(u32, TankState)>,
+
+    // shared by every AI controller
+    rng: Rng,
+
+    // the last wall the player shook, for the renderer
+    wall_shake: Option<(Direction, Timer)>,
+
+    // past states, for undo and rewind
+    history: History,
 }
 
 impl State {
-    pub(super) fn new() -> Self {
-        let static_block_types = Array2D::from_box(
-            5,
-            5,
-            Box::new([
-                Some(BlockType::Solid),
-                Some(BlockType::Solid),
-                Some(BlockType::Solid),
-                Some(BlockType::Solid),
-                Some(BlockType::Solid),
-                Some(BlockType::Solid),
-                None,
-                None,
-                None,
-                Some(BlockType::Solid),
-                Some(BlockType::Solid),
-                None,
-                Some(BlockType::Normal),
-                None,
-                Some(BlockType::Solid),
-                Some(BlockType::Solid),
-                None,
-                None,
-                None,
-                Some(BlockType::Solid),
-                Some(BlockType::Solid),
-                Some(BlockType::Solid),
-                Some(BlockType::Solid),
-                Some(BlockType::Solid),
-                Some(BlockType::Solid),
-            ]),
-        );
+    pub(super) fn from_level(level: &Level) -> Self {
+        let static_block_types = level.static_block_types.clone();
 
-        let sliding_block_ids = vec![0];
-        let sliding_block_positions = vec![Vec2(0., 64.)];
-        let sliding_block_directions = vec![Direction::Right];
-        let sliding_block_types = vec![BlockType::Normal];
+        // tanks and sliding blocks share one id space
+        let mut id_counter = 0;
+        let mut next_id = || {
+            id_counter += 1;
+            id_counter - 1
+        };
 
-        let tank_ids = vec![0];
-        let tank_positions = vec![Vec2(16., 16.)];
-        let tank_directions = vec![Direction::Up];
-        let tank_states = vec![TankState::Idle];
+        let sliding_block_ids = level.sliding_blocks.iter().map(|_| next_id()).collect();
+        let sliding_block_positions = level
+            .sliding_blocks
+            .iter()
+            .map(|block| Vec2((block.x * 16) as f32, (block.y * 16) as f32))
+            .collect();
+        let sliding_block_directions = level
+            .sliding_blocks
+            .iter()
+            .map(|block| block.direction)
+            .collect();
+        let sliding_block_types = level
+            .sliding_blocks
+            .iter()
+            .map(|block| block.block_type)
+            .collect();
 
-        Self {
+        let tank_ids = level.tanks.iter().map(|_| next_id()).collect();
+        let tank_positions: Vec<_> = level
+            .tanks
+            .iter()
+            .map(|tank| Vec2((tank.x * 16) as f32, (tank.y * 16) as f32))
+            .collect();
+        let tank_directions = level.tanks.iter().map(|tank| tank.direction).collect();
+        let tank_states = level.tanks.iter().map(|_| TankState::Idle).collect();
+        let tank_controllers: Vec<_> = level
+            .tanks
+            .iter()
+            .map(|tank| tank.controller.clone())
+            .collect();
+        let tank_shields = level.tanks.iter().map(|_| 0).collect();
+
+        // the level guarantees exactly one player
+        let player_spawn = tank_controllers
+            .iter()
+            .position(|c| match c {
+                Controller::Player => true,
+                _ => false,
+            })
+            .map_or(Vec2(16., 16.), |index| tank_positions[index]);
+
+        let mut state = Self {
             exit: false,
+            tick: 0,
+            events: vec![],
+            paused: false,
+            clock: 0,
             upkey_down: false,
             downkey_down: false,
             leftkey_down: false,
             rightkey_down: false,
             zkey_down: false,
+            xkey_down: false,
             upkey_was_down: false,
             downkey_was_down: false,
             leftkey_was_down: false,
             rightkey_was_down: false,
             zkey_was_down: false,
+            xkey_was_down: false,
+
+            rkey_down: false,
+            pkey_down: false,
+            ekey_down: false,
+            ukey_down: false,
+            f5key_down: false,
+            f9key_down: false,
+
+            window_size: Vec2(160., 144.),
+            cursor: Vec2(0., 0.),
+            lmb_down: false,
+            rmb_down: false,
+            lmb_was_down: false,
 
+            editor: None,
+
+            game_state: GameState::Playing,
             camera: Vec4(0f32, 0f32, 160f32, 144f32),
-            rem_time: 66,
-            rem_tanks: 8,
+            time_limit: level.time * 1_000_000,
+            rem_time: level.time * 1_000_000,
+            rem_lives: level.lives - 1,
+            rem_tanks: 0,
+            kills: 0,
+            score: 0,
+            lives: level.lives,
+            player_spawn,
+            tank_spawns: level.tanks.clone(),
+            hatch: level.hatch.iter().map(|time| time * 1_000_000).collect(),
+
+            spawn_points: level.spawns.clone(),
+            waves: level
+                .waves
+                .iter()
+                .map(|wave| Wave {
+                    time: wave.time * 1_000_000,
+                    tanks: wave.tanks.clone(),
+                })
+                .collect(),
+            wave_queue: vec![],
 
-            id_counter: 0,
+            id_counter,
 
             static_block_types,
+            terrain: level.terrain.clone(),
 
             sliding_block_ids,
             sliding_block_positions,
             sliding_block_directions,
             sliding_block_types,
 
+            projectile_ids: vec![],
+            projectile_positions: vec![],
+            projectile_directions: vec![],
+            projectile_owners: vec![],
+            projectile_speeds: vec![],
+
             tank_ids,
             tank_positions,
             tank_directions,
             tank_states,
-        }
+            tank_controllers,
+            tank_shields,
+            tank_schedule: Scheduler::new(),
+
+            rng: Rng::new(0),
+
+            wall_shake: None,
+
+            history: History::new(),
+        };
+        state.rem_tanks = state.enemies_left();
+        state
     }
 
     pub(super) fn exit(&self) -> bool {
         self.exit
     }
 
-    pub(super) fn pre_step(
-        &mut self,
-        _us_frame_timestamp: u64,
-        events: impl Iterator<Item = Event>,
-    ) {
+    pub(super) fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub(super) fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub(super) fn pre_step(&mut self, events: impl Iterator<Item = Event>) {
+        // inputs take effect on the next tick to be simulated
+        let tick = self.tick;
+        self.events.extend(events.map(|event| (tick, event)));
+    }
+
+    fn process_events(&mut self) {
         self.upkey_was_down = self.upkey_down;
         self.downkey_was_down = self.downkey_down;
         self.rightkey_was_down = self.rightkey_down;
         self.leftkey_was_down = self.leftkey_down;
         self.zkey_was_down = self.zkey_down;
+        self.xkey_was_down = self.xkey_down;
+        self.lmb_was_down = self.lmb_down;
+
+        // pull out this tick's events, leaving any later ones queued
+        let tick = self.tick;
+        let (events, later) = std::mem::replace(&mut self.events, vec![])
+            .into_iter()
+            .partition(|&(event_tick, _)| event_tick <= tick);
+        self.events = later;
 
         use super::event::Key;
-        for event in events {
+        for (_, event) in events {
             match event {
                 Event::Exit => dbg!(self.exit = true),
+                Event::KeyDown(Key::P) => {
+                    if !self.pkey_down {
+                        self.paused = !self.paused;
+                    }
+                    self.pkey_down = true;
+                }
+                Event::KeyUp(Key::P) => self.pkey_down = false,
                 Event::KeyDown(Key::Z) => dbg!(self.zkey_down = true),
+                Event::KeyDown(Key::X) => self.xkey_down = true,
                 Event::KeyDown(Key::Up) => dbg!(self.upkey_down = true),
                 Event::KeyDown(Key::Down) => dbg!(self.downkey_down = true),
                 Event::KeyDown(Key::Left) => dbg!(self.leftkey_down = true),
                 Event::KeyDown(Key::Right) => dbg!(self.rightkey_down = true),
                 Event::KeyUp(Key::Z) => dbg!(self.zkey_down = false),
+                Event::KeyUp(Key::X) => self.xkey_down = false,
                 Event::KeyUp(Key::Up) => dbg!(self.upkey_down = false),
                 Event::KeyUp(Key::Down) => dbg!(self.downkey_down = false),
                 Event::KeyUp(Key::Left) => dbg!(self.leftkey_down = false),
                 Event::KeyUp(Key::Right) => dbg!(self.rightkey_down = false),
+                Event::KeyDown(Key::Lmb) => self.lmb_down = true,
+                Event::KeyDown(Key::Rmb) => self.rmb_down = true,
+                Event::KeyUp(Key::Lmb) => self.lmb_down = false,
+                Event::KeyUp(Key::Rmb) => self.rmb_down = false,
+                Event::MouseMove(x, y) => self.cursor = Vec2(x, y),
+                Event::Resize(w, h) => self.window_size = Vec2(w, h),
+                Event::KeyDown(Key::E) => {
+                    if !self.ekey_down {
+                        self.toggle_editor();
+                    }
+                    self.ekey_down = true;
+                }
+                Event::KeyUp(Key::E) => self.ekey_down = false,
+                Event::KeyDown(Key::F5) => {
+                    if !self.f5key_down {
+                        self.quick_save();
+                    }
+                    self.f5key_down = true;
+                }
+                Event::KeyUp(Key::F5) => self.f5key_down = false,
+                Event::KeyDown(Key::F9) => {
+                    if !self.f9key_down {
+                        self.quick_load();
+                    }
+                    self.f9key_down = true;
+                }
+                Event::KeyUp(Key::F9) => self.f9key_down = false,
+                Event::KeyDown(Key::U) => {
+                    if !self.ukey_down {
+                        self.undo();
+                    }
+                    self.ukey_down = true;
+                }
+                Event::KeyUp(Key::U) => self.ukey_down = false,
+                Event::KeyDown(Key::R) => self.rkey_down = true,
+                Event::KeyUp(Key::R) => self.rkey_down = false,
+                Event::KeyDown(key) => {
+                    if let Some(editor) = &mut self.editor {
+                        match key {
+                            Key::B => editor.next_block(),
+                            Key::T => editor.next_tank(),
+                            Key::S => self.save_level(),
+                            _ => {}
+                        }
+                    }
+                }
                 _ => {}
             }
         }
     }
 
-    pub(super) fn step(&mut self, us_frame_timestamp: u64, simtime: u64) {
-        let dt = simtime as f32 / 1000000f32;
+    pub(super) fn step(&mut self) {
+        let us_frame_timestamp = self.clock;
 
-        // temp camera movement
-        let Vec2(x, y) = *self.tank_positions.get(0).unwrap_or(&Vec2(0., 0.));
-        self.camera.0 = x - self.camera.2 / 2.;
-        self.camera.1 = y - self.camera.3 / 2.;
+        // apply this tick's input
+        self.process_events();
+        self.tick += 1;
+
+        // the world stands still while it's being edited
+        if self.editor.is_some() {
+            self.editor_step();
+            return;
+        }
+
+        // scrub back through recent ticks while rewind is held, even out of a game over
+        if self.rkey_down {
+            if let Some(snapshot) = self.history.rewind() {
+                self.restore(snapshot);
+            }
+            return;
+        }
+
+        // nothing moves while paused, or once the round is over
+        if self.paused || self.game_state != GameState::Playing {
+            return;
+        }
+        self.history.record(&self.snapshot());
+        self.clock += US_PER_TICK;
+
+        // run the level clock down
+        self.rem_time = self.rem_time.saturating_sub(US_PER_TICK);
+        if self.rem_time == 0 {
+            self.time_up(us_frame_timestamp);
+        }
+
+        // temp camera movement, following the player
+        if let Some(index) = self.player_index() {
+            let Vec2(x, y) = self.tank_positions[index];
+            self.camera.0 = x - self.camera.2 / 2.;
+            self.camera.1 = y - self.camera.3 / 2.;
+        }
 
         // clamp
         if self.camera.0 < 0f32 {
@@ -172,32 +419,71 @@ impl State {
             self.camera.1 = 0f32;
         }
 
-        // process tank delay
-        tank_delay(
+        // process scheduled tank transitions
+        tank_transitions(
             self.tank_ids.len(),
             us_frame_timestamp,
+            &self.tank_ids,
             &mut self.tank_states,
+            &mut self.tank_schedule,
         );
 
-        // process tank AI
-        let (push, mov) = tank_ai(
+        // eggs due this tick break open into new enemies
+        let due = self
+            .hatch
+            .iter()
+            .filter(|&&at| at >= us_frame_timestamp && at < self.clock)
+            .count();
+        for _ in 0..due {
+            self.hatch_egg(us_frame_timestamp);
+        }
+
+        // waves due this tick line up at the spawn points, and go in as they come clear
+        for wave in &self.waves {
+            if wave.time >= us_frame_timestamp && wave.time < self.clock {
+                self.wave_queue.extend(wave.tanks.iter().cloned());
+            }
+        }
+        while !self.wave_queue.is_empty() && self.send_wave_tank(us_frame_timestamp) {}
+
+        // a player starting a move or push is something to undo back to
+        let player_was_idle = match self.player_index().map(|index| self.tank_states[index]) {
+            Some(TankState::Idle) => true,
+            _ => false,
+        };
+
+        // process tank controllers
+        let player_input = PlayerInput {
+            push: self.zkey_down & !self.zkey_was_down,
+            fire: self.xkey_down & !self.xkey_was_down,
+            up: self.upkey_down,
+            down: self.downkey_down,
+            left: self.leftkey_down,
+            right: self.rightkey_down,
+        };
+        let (push, mov, fire) = tank_control(
             self.tank_ids.len(),
+            player_input,
+            &mut self.rng,
+            &self.tank_positions,
+            &self.tank_directions,
             &self.tank_states,
-            self.zkey_down & !self.zkey_was_down,
-            self.upkey_down,
-            self.downkey_down,
-            self.leftkey_down,
-            self.rightkey_down,
+            &mut self.tank_controllers,
+            &self.static_block_types,
+            &self.terrain,
         );
 
         // process push
-        tank_push_command(
+        let (shakes, smashed) = tank_push_command(
             push,
             us_frame_timestamp,
             &mut self.static_block_types,
+            &self.terrain,
+            &self.tank_ids,
             &self.tank_positions,
             &self.tank_directions,
             &mut self.tank_states,
+            &mut self.tank_schedule,
             &mut self.id_counter,
             &mut self.sliding_block_ids,
             &mut self.sliding_block_positions,
@@ -205,6 +491,30 @@ impl State {
             &mut self.sliding_block_types,
         );
 
+        // process fire
+        tank_fire_command(
+            fire,
+            us_frame_timestamp,
+            &self.tank_ids,
+            &self.tank_positions,
+            &self.tank_directions,
+            &mut self.tank_states,
+            &mut self.tank_schedule,
+            &mut self.id_counter,
+            &mut self.projectile_ids,
+            &mut self.projectile_positions,
+            &mut self.projectile_directions,
+            &mut self.projectile_owners,
+            &mut self.projectile_speeds,
+        );
+
+        // eggs only pay out when the player breaks them
+        for &index in &smashed {
+            if let Controller::Player = self.tank_controllers[index] {
+                self.score += EGG_SCORE;
+            }
+        }
+
         // process tank move
         tank_move_command(
             mov,
@@ -213,7 +523,41 @@ impl State {
             &mut self.tank_directions,
             &mut self.tank_states,
             &self.static_block_types,
+            &self.terrain,
+        );
+
+        if player_was_idle {
+            match self.player_index().map(|index| self.tank_states[index]) {
+                Some(TankState::Moving { .. }) | Some(TankState::Delayed) => {
+                    self.history.checkpoint()
+                }
+                _ => {}
+            }
+        }
+
+        // shoving the outer wall stuns the enemies along it, stopping them mid move
+        wall_shake(
+            &shakes,
+            us_frame_timestamp,
+            self.static_block_types.get_width(),
+            self.static_block_types.get_height(),
+            self.tank_ids.len(),
+            &self.tank_ids,
+            &mut self.tank_positions,
+            &self.tank_controllers,
+            &mut self.tank_states,
+            &mut self.tank_schedule,
         );
+        if let Some(&(_, side)) =
+            shakes
+                .iter()
+                .find(|&&(index, _)| match self.tank_controllers[index] {
+                    Controller::Player => true,
+                    _ => false,
+                })
+        {
+            self.wall_shake = Some((side, Timer::new(us_frame_timestamp, WALL_SHAKE_TIME)));
+        }
 
         // process tank movement
         tank_movement(
@@ -222,43 +566,823 @@ impl State {
             &mut self.tank_positions,
             &mut self.tank_directions,
             &mut self.tank_states,
+            &self.static_block_types,
+            &self.terrain,
+        );
+
+        // pick up time bonuses, topping the clock up no further than a level's can start at
+        let pickups = time_bonus_pickup(
+            self.tank_ids.len(),
+            &self.tank_positions,
+            &self.tank_controllers,
+            &mut self.static_block_types,
         );
+        self.rem_time = self
+            .rem_time
+            .saturating_add(pickups * TIME_BONUS)
+            .min(MAX_TIME * 1_000_000);
 
         // sliding block movement
-        sliding_block_movement(
-            self.sliding_block_ids.len(),
-            dt,
+        let settled = sliding_block_movement(
+            &mut self.static_block_types,
+            &mut self.terrain,
+            &mut self.sliding_block_ids,
             &mut self.sliding_block_positions,
-            &self.sliding_block_directions,
+            &mut self.sliding_block_directions,
+            &mut self.sliding_block_types,
+        );
+
+        // lining up diamonds pays out and stuns every enemy
+        let lines = diamond_lines(&self.static_block_types, &settled);
+        if lines > 0 {
+            self.score += lines * DIAMOND_LINE_SCORE;
+            stun_enemies(
+                self.tank_ids.len(),
+                us_frame_timestamp,
+                &self.tank_ids,
+                &mut self.tank_positions,
+                &self.tank_controllers,
+                &mut self.tank_states,
+                &mut self.tank_schedule,
+            );
+        }
+
+        // projectile movement, stopping at blocks
+        let base_destroyed = projectile_movement(
+            &mut self.static_block_types,
+            &self.sliding_block_positions,
+            &mut self.projectile_ids,
+            &mut self.projectile_positions,
+            &mut self.projectile_directions,
+            &mut self.projectile_owners,
+            &mut self.projectile_speeds,
+        );
+
+        // and at tanks, which they destroy
+        let kills = projectile_tank_collision(
+            self.tank_ids.len(),
+            us_frame_timestamp,
+            &mut self.projectile_ids,
+            &mut self.projectile_positions,
+            &mut self.projectile_directions,
+            &mut self.projectile_owners,
+            &mut self.projectile_speeds,
+            &self.tank_ids,
+            &self.tank_positions,
+            &self.tank_controllers,
+            &mut self.tank_states,
+            &self.tank_shields,
+            &mut self.tank_schedule,
+        );
+        self.kills += kills as u32;
+
+        // crush any tanks in the way of sliding blocks
+        let kills = sliding_block_tank_collision(
+            self.sliding_block_ids.len(),
+            self.tank_ids.len(),
+            us_frame_timestamp,
+            &self.sliding_block_positions,
+            &self.tank_ids,
+            &self.tank_positions,
+            &self.tank_controllers,
+            &mut self.tank_states,
+            &self.tank_shields,
+            &mut self.tank_schedule,
+        );
+        self.kills += kills as u32;
+
+        // and any stunned ones the player drives into
+        let kills = stunned_tank_collision(
+            self.tank_ids.len(),
+            us_frame_timestamp,
+            &self.tank_ids,
+            &self.tank_positions,
+            &self.tank_controllers,
+            &mut self.tank_states,
+            &mut self.tank_schedule,
+        );
+        self.kills += kills as u32;
+
+        // remove tanks that have finished exploding
+        tank_cleanup(
+            &mut self.tank_ids,
+            &mut self.tank_positions,
+            &mut self.tank_directions,
+            &mut self.tank_states,
+            &mut self.tank_controllers,
+            &mut self.tank_shields,
+        );
+
+        // a destroyed player costs a life
+        if self.player_index().is_none() {
+            self.lose_life(us_frame_timestamp);
+        }
+
+        // losing the base loses the round outright, and beating every wave wins it
+        if base_destroyed {
+            self.game_state = GameState::GameOver;
+        }
+        self.rem_tanks = self.enemies_left();
+        if !self.waves.is_empty() && self.cleared() {
+            self.game_state = GameState::Won;
+        }
+    }
+
+    fn toggle_editor(&mut self) {
+        match self.editor {
+            Some(_) => self.editor = None,
+            None => {
+                // edits don't go through the history, so it no longer lines up
+                self.history.clear();
+
+                // tanks go back to their spawns, but blocks stay wherever play left them
+                self.editor = Some(Editor::new());
+                self.reset_tanks();
+            }
+        }
+    }
+
+    fn editor_step(&mut self) {
+        // pan with the arrow keys, staying over the map
+        const PAN_SPEED: f32 = 2.; // pixels per tick
+        if self.leftkey_down {
+            self.camera.0 -= PAN_SPEED;
+        }
+        if self.rightkey_down {
+            self.camera.0 += PAN_SPEED;
+        }
+        if self.upkey_down {
+            self.camera.1 -= PAN_SPEED;
+        }
+        if self.downkey_down {
+            self.camera.1 += PAN_SPEED;
+        }
+        let map_w = (self.static_block_types.get_width() * 16) as f32;
+        let map_h = (self.static_block_types.get_height() * 16) as f32;
+        self.camera.0 = clamp(0., self.camera.0, (map_w - self.camera.2).max(0.));
+        self.camera.1 = clamp(0., self.camera.1, (map_h - self.camera.3).max(0.));
+
+        let cell = match self.cursor_cell() {
+            Some(cell) => cell,
+            None => return,
+        };
+        let brush = match &self.editor {
+            Some(editor) => editor.brush,
+            None => return,
+        };
+        let spawn_here = self
+            .tank_spawns
+            .iter()
+            .position(|spawn| (spawn.x, spawn.y) == cell);
+        let sliding_here = self
+            .sliding_block_cells()
+            .iter()
+            .any(|&(sliding_cell, _)| sliding_cell == cell);
+        let spawn_point_here = self.spawn_points.contains(&cell);
+
+        // the wall around the edge stays put, keeping everything inside the map
+        let (width, height) = (
+            self.static_block_types.get_width(),
+            self.static_block_types.get_height(),
+        );
+        if on_border(width, height, cell) {
+            return;
+        }
+
+        // right click clears the cell, other than the player who can only be moved
+        if self.rmb_down {
+            self.static_block_types[cell] = None;
+            if let Some(index) = spawn_here {
+                if let Controller::Player = self.tank_spawns[index].controller {
+                    return;
+                }
+                self.tank_spawns.remove(index);
+                self.reset_tanks();
+            }
+            return;
+        }
+
+        match brush {
+            // blocks paint as the mouse is dragged, but never over a tank, a sliding block
+            // or where wave tanks come in
+            Brush::Block(index)
+                if self.lmb_down && spawn_here.is_none() && !sliding_here && !spawn_point_here =>
+            {
+                self.static_block_types[cell] = Some(BLOCK_PALETTE[index]);
+            }
+
+            // tanks are placed one per click, onto empty cells
+            Brush::Tank(index) if self.lmb_down && !self.lmb_was_down => {
+                if self.static_block_types[cell].is_some() {
+                    return;
+                }
+                let controller = tank_palette(index);
+                if let Controller::Player = controller {
+                    self.tank_spawns.retain(|spawn| match spawn.controller {
+                        Controller::Player => false,
+                        _ => true,
+                    });
+                    self.player_spawn = Vec2((cell.0 * 16) as f32, (cell.1 * 16) as f32);
+                }
+                self.tank_spawns.retain(|spawn| (spawn.x, spawn.y) != cell);
+                self.tank_spawns.push(TankSpawn {
+                    x: cell.0,
+                    y: cell.1,
+                    direction: Direction::Up,
+                    controller,
+                });
+                self.reset_tanks();
+            }
+
+            _ => {}
+        }
+    }
+
+    // the map cell under the mouse
+    fn cursor_cell(&self) -> Option<(usize, usize)> {
+        let Vec2(x, y) = screen_to_world(self.cursor, self.window_size, self.camera);
+        if x < 0. || y < 0. {
+            return None;
+        }
+        let cell = (x as usize / 16, y as usize / 16);
+        self.static_block_types.coord_get(cell.0, cell.1)?;
+        Some(cell)
+    }
+
+    // puts every tank back at its spawn, dropping any shots in the air and wave tanks
+    // still waiting to come in
+    fn reset_tanks(&mut self) {
+        self.wave_queue.clear();
+
+        self.projectile_ids.clear();
+        self.projectile_positions.clear();
+        self.projectile_directions.clear();
+        self.projectile_owners.clear();
+        self.projectile_speeds.clear();
+
+        self.tank_ids.clear();
+        self.tank_positions.clear();
+        self.tank_directions.clear();
+        self.tank_states.clear();
+        self.tank_controllers.clear();
+        self.tank_shields.clear();
+        self.tank_schedule = Scheduler::new();
+
+        for spawn in self.tank_spawns.clone() {
+            let pos = Vec2((spawn.x * 16) as f32, (spawn.y * 16) as f32);
+            self.spawn_tank(pos, spawn.direction, spawn.controller, 0);
+        }
+    }
+
+    fn save_level(&self) {
+        let level = Level {
+            time: self.time_limit / 1_000_000,
+            lives: self.lives,
+            hatch: self.hatch.iter().map(|time| time / 1_000_000).collect(),
+            spawns: self.spawn_points.clone(),
+            waves: self
+                .waves
+                .iter()
+                .map(|wave| Wave {
+                    time: wave.time / 1_000_000,
+                    tanks: wave.tanks.clone(),
+                })
+                .collect(),
+            static_block_types: self.static_block_types.clone(),
+            terrain: self.terrain.clone(),
+            tanks: self.tank_spawns.clone(),
+            sliding_blocks: self
+                .sliding_block_cells()
+                .into_iter()
+                .map(|((x, y), index)| SlidingBlockSpawn {
+                    x,
+                    y,
+                    direction: self.sliding_block_directions[index],
+                    block_type: self.sliding_block_types[index],
+                })
+                .collect(),
+        };
+
+        // kept out of resources/levels, so it isn't taken for one of the real levels
+        let dir = crate::io::get_root().join("resources/edited");
+        let path = dir.join("edited.lvl");
+        match std::fs::create_dir_all(&dir).and_then(|_| level.save(&path)) {
+            Ok(()) => println!("Level saved: {}", path.display()),
+            Err(err) => println!("Level not saved: {}: {}", path.display(), err),
+        }
+    }
+
+    // the nearest cell to each block still in flight, which it'll carry on from if saved
+    fn sliding_block_cells(&self) -> Vec<((usize, usize), usize)> {
+        (0..self.sliding_block_ids.len())
+            .map(|index| {
+                let Vec2(x, y) = self.sliding_block_positions[index];
+                let cell = ((x / 16.).round() as usize, (y / 16.).round() as usize);
+                (cell, index)
+            })
+            .collect()
+    }
+
+    // swaps in a different level, keeping the input and tick count going
+    pub(super) fn load_level(&mut self, level: &Level) {
+        self.restore(State::from_level(level).snapshot());
+        self.history.clear();
+    }
+
+    // whether every enemy has been destroyed, including any still to come in waves
+    pub(super) fn cleared(&self) -> bool {
+        match self.game_state {
+            GameState::Playing => {}
+            GameState::Won => return true,
+            GameState::GameOver => return false,
+        }
+        self.wave_queue.is_empty()
+            && self.waves.iter().all(|wave| wave.time < self.clock)
+            && self.eggs().is_empty()
+            && !self.sliding_block_types.contains(&BlockType::Egg)
+            && self.tank_controllers.iter().all(|c| match c {
+                Controller::Player => true,
+                _ => false,
+            })
+    }
+
+    pub(super) fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            clock: self.clock,
+            game_state: self.game_state,
+            camera: self.camera,
+            time_limit: self.time_limit,
+            rem_time: self.rem_time,
+            rem_lives: self.rem_lives,
+            rem_tanks: self.rem_tanks,
+            kills: self.kills,
+            score: self.score,
+            lives: self.lives,
+            player_spawn: self.player_spawn,
+            tank_spawns: self.tank_spawns.clone(),
+            hatch: self.hatch.clone(),
+            spawn_points: self.spawn_points.clone(),
+            waves: self.waves.clone(),
+            wave_queue: self.wave_queue.clone(),
+            static_block_types: self.static_block_types.clone(),
+            terrain: self.terrain.clone(),
+            id_counter: self.id_counter,
+            sliding_block_ids: self.sliding_block_ids.clone(),
+            sliding_block_positions: self.sliding_block_positions.clone(),
+            sliding_block_directions: self.sliding_block_directions.clone(),
+            sliding_block_types: self.sliding_block_types.clone(),
+            projectile_ids: self.projectile_ids.clone(),
+            projectile_positions: self.projectile_positions.clone(),
+            projectile_directions: self.projectile_directions.clone(),
+            projectile_owners: self.projectile_owners.clone(),
+            projectile_speeds: self.projectile_speeds.clone(),
+            tank_ids: self.tank_ids.clone(),
+            tank_positions: self.tank_positions.clone(),
+            tank_directions: self.tank_directions.clone(),
+            tank_states: self.tank_states.clone(),
+            tank_controllers: self.tank_controllers.clone(),
+            tank_shields: self.tank_shields.clone(),
+            tank_schedule: self.tank_schedule.entries().to_vec(),
+            rng: self.rng.state(),
+        }
+    }
+
+    // input, the tick count and pausing carry on as they were
+    pub(super) fn restore(&mut self, snapshot: Snapshot) {
+        self.clock = snapshot.clock;
+        self.game_state = snapshot.game_state;
+        self.camera = snapshot.camera;
+        self.time_limit = snapshot.time_limit;
+        self.rem_time = snapshot.rem_time;
+        self.rem_lives = snapshot.rem_lives;
+        self.rem_tanks = snapshot.rem_tanks;
+        self.kills = snapshot.kills;
+        self.score = snapshot.score;
+        self.lives = snapshot.lives;
+        self.player_spawn = snapshot.player_spawn;
+        self.tank_spawns = snapshot.tank_spawns;
+        self.hatch = snapshot.hatch;
+        self.spawn_points = snapshot.spawn_points;
+        self.waves = snapshot.waves;
+        self.wave_queue = snapshot.wave_queue;
+        self.static_block_types = snapshot.static_block_types;
+        self.terrain = snapshot.terrain;
+        self.id_counter = snapshot.id_counter;
+        self.sliding_block_ids = snapshot.sliding_block_ids;
+        self.sliding_block_positions = snapshot.sliding_block_positions;
+        self.sliding_block_directions = snapshot.sliding_block_directions;
+        self.sliding_block_types = snapshot.sliding_block_types;
+        self.projectile_ids = snapshot.projectile_ids;
+        self.projectile_positions = snapshot.projectile_positions;
+        self.projectile_directions = snapshot.projectile_directions;
+        self.projectile_owners = snapshot.projectile_owners;
+        self.projectile_speeds = snapshot.projectile_speeds;
+        self.tank_ids = snapshot.tank_ids;
+        self.tank_positions = snapshot.tank_positions;
+        self.tank_directions = snapshot.tank_directions;
+        self.tank_states = snapshot.tank_states;
+        self.tank_controllers = snapshot.tank_controllers;
+        self.tank_shields = snapshot.tank_shields;
+        self.tank_schedule = Scheduler::new();
+        for (at, transition) in snapshot.tank_schedule {
+            self.tank_schedule.schedule(at, transition);
+        }
+        self.rng = Rng::from_state(snapshot.rng);
+    }
+
+    // back to before the player's last move or push
+    fn undo(&mut self) {
+        if self.editor.is_some() {
+            return;
+        }
+        if let Some(snapshot) = self.history.undo() {
+            self.restore(snapshot);
+        }
+    }
+
+    fn quick_save(&self) {
+        let dir = crate::io::get_root().join("resources/saves");
+        let path = dir.join("quick.sav");
+        let result = std::fs::create_dir_all(&dir).and_then(|_| self.snapshot().save(&path));
+        match result {
+            Ok(()) => println!("Game saved: {}", path.display()),
+            Err(err) => println!("Game not saved: {}: {}", path.display(), err),
+        }
+    }
+
+    fn quick_load(&mut self) {
+        let path = crate::io::get_root().join("resources/saves/quick.sav");
+        match Snapshot::open(&path) {
+            Ok(snapshot) => {
+                self.restore(snapshot);
+                self.history.clear();
+                println!("Game loaded: {}", path.display());
+            }
+            Err(err) => println!("Game not loaded: {}: {}", path.display(), err),
+        }
+    }
+
+    fn player_index(&self) -> Option<usize> {
+        self.tank_controllers.iter().position(|c| match c {
+            Controller::Player => true,
+            _ => false,
+        })
+    }
+
+    fn time_up(&mut self, us_timestamp: u64) {
+        // running out of time blows up the player, and the clock starts over for the next life
+        for index in 0..self.tank_ids.len() {
+            match (&self.tank_controllers[index], self.tank_states[index]) {
+                (_, TankState::Exploding) | (_, TankState::Dead) => continue,
+                (Controller::Player, _) => {}
+                _ => continue,
+            }
+            tank_destroy(
+                index,
+                us_timestamp,
+                &self.tank_ids,
+                &mut self.tank_states,
+                &mut self.tank_schedule,
+            );
+        }
+        self.rem_time = self.time_limit;
+    }
+
+    fn lose_life(&mut self, us_timestamp: u64) {
+        if self.rem_lives == 0 {
+            self.game_state = GameState::GameOver;
+            return;
+        }
+        self.rem_lives -= 1;
+
+        // respawn briefly invulnerable
+        self.spawn_tank(
+            self.player_spawn,
+            Direction::Up,
+            Controller::Player,
+            us_timestamp + TANK_SPAWN_SHIELD,
         );
     }
 
-    pub(super) fn post_step(&mut self, _timestamp: u64) {}
+    fn spawn_tank(
+        &mut self,
+        pos: Vec2f,
+        dir: Direction,
+        controller: Controller,
+        us_shield: u64,
+    ) -> u32 {
+        let id = self.id_counter;
+        self.id_counter += 1;
+
+        self.tank_ids.push(id);
+        self.tank_positions.push(pos);
+        self.tank_directions.push(dir);
+        self.tank_states.push(TankState::Idle);
+        self.tank_controllers.push(controller);
+        self.tank_shields.push(us_shield);
+
+        id
+    }
+
+    fn eggs(&self) -> Vec<(usize, usize)> {
+        let mut eggs = vec![];
+        self.static_block_types.for_each(|i, j, &block_type| {
+            if block_type == Some(BlockType::Egg) {
+                eggs.push((i, j));
+            }
+        });
+        eggs
+    }
+
+    // a random egg hatches into a random enemy, briefly invulnerable like any spawn
+    fn hatch_egg(&mut self, us_timestamp: u64) {
+        let eggs = self.eggs();
+        if eggs.is_empty() {
+            return;
+        }
+        let (x, y) = eggs[self.rng.range(eggs.len() as u32) as usize];
+        self.static_block_types[(x, y)] = None;
+
+        let controller = match self.rng.range(2) {
+            0 => Controller::Wander,
+            _ => Controller::Chase,
+        };
+        let dir = self.rng.direction();
+        self.spawn_tank(
+            Vec2((x * 16) as f32, (y * 16) as f32),
+            dir,
+            controller,
+            us_timestamp + TANK_SPAWN_SHIELD,
+        );
+    }
+
+    // the next queued enemy comes in at a random spawn point with nothing on or over it,
+    // returning false if they're all taken
+    fn send_wave_tank(&mut self, us_timestamp: u64) -> bool {
+        let clear = |&(x, y): &(usize, usize)| {
+            let (cx, cy) = ((x * 16) as f32, (y * 16) as f32);
+            let apart = |&Vec2(px, py): &Vec2f| (px - cx).abs() >= 16. || (py - cy).abs() >= 16.;
+            self.static_block_types[(x, y)].is_none()
+                && self.tank_positions.iter().all(apart)
+                && self.sliding_block_positions.iter().all(apart)
+        };
+        let free: Vec<_> = self.spawn_points.iter().cloned().filter(clear).collect();
+        if free.is_empty() {
+            return false;
+        }
+        let (x, y) = free[self.rng.range(free.len() as u32) as usize];
+
+        let controller = self.wave_queue.remove(0);
+        self.spawn_tank(
+            Vec2((x * 16) as f32, (y * 16) as f32),
+            Direction::Down,
+            controller,
+            us_timestamp + TANK_SPAWN_SHIELD,
+        );
+        true
+    }
+
+    // enemies on the map, waiting to come in, or in waves still to come
+    fn enemies_left(&self) -> u8 {
+        let on_map = (0..self.tank_ids.len())
+            .filter(
+                |&index| match (&self.tank_controllers[index], self.tank_states[index]) {
+                    (Controller::Player, _) => false,
+                    (_, TankState::Exploding) | (_, TankState::Dead) => false,
+                    _ => true,
+                },
+            )
+            .count();
+        let to_come: usize = self
+            .waves
+            .iter()
+            .filter(|wave| wave.time >= self.clock)
+            .map(|wave| wave.tanks.len())
+            .sum();
+        (on_map + self.wave_queue.len() + to_come).min(255) as u8
+    }
+
+    pub(super) fn post_step(&mut self) {}
+
+    // a plain text description of the whole simulation, for comparing runs
+    pub(super) fn dump(&self) -> String {
+        use std::fmt::Write;
+        let mut out = String::new();
+
+        let _ = writeln!(out, "tick {}", self.tick);
+        let _ = writeln!(out, "clock {}", self.clock);
+        let _ = writeln!(out, "state {:?}", self.game_state);
+        let _ = writeln!(out, "time {}", self.rem_time);
+        let _ = writeln!(out, "lives {}", self.rem_lives);
+        let _ = writeln!(out, "enemies {}", self.rem_tanks);
+        let _ = writeln!(out, "kills {}", self.kills);
+        let _ = writeln!(out, "score {}", self.score);
+
+        for index in 0..self.tank_ids.len() {
+            let Vec2(x, y) = self.tank_positions[index];
+            let _ = writeln!(
+                out,
+                "tank {} {} {} {} {:?} {:?}",
+                self.tank_ids[index],
+                x,
+                y,
+                direction_name(self.tank_directions[index]),
+                self.tank_states[index],
+                self.tank_controllers[index],
+            );
+        }
+
+        for index in 0..self.sliding_block_ids.len() {
+            let Vec2(x, y) = self.sliding_block_positions[index];
+            let _ = writeln!(
+                out,
+                "slide {} {} {} {} {}",
+                self.sliding_block_ids[index],
+                x,
+                y,
+                direction_name(self.sliding_block_directions[index]),
+                block_to_char(Some(self.sliding_block_types[index])),
+            );
+        }
+
+        for index in 0..self.projectile_ids.len() {
+            let Vec2(x, y) = self.projectile_positions[index];
+            let _ = writeln!(
+                out,
+                "shot {} {} {} {} {} {}",
+                self.projectile_ids[index],
+                x,
+                y,
+                direction_name(self.projectile_directions[index]),
+                self.projectile_owners[index],
+                self.projectile_speeds[index],
+            );
+        }
+
+        let _ = writeln!(out, "map");
+        for y in 0..self.static_block_types.get_height() {
+            for x in 0..self.static_block_types.get_width() {
+                out.push(block_to_char(self.static_block_types[(x, y)]));
+            }
+            out.push('\n');
+        }
+
+        let _ = writeln!(out, "terrain");
+        for y in 0..self.terrain.get_height() {
+            for x in 0..self.terrain.get_width() {
+                out.push(terrain_to_char(self.terrain[(x, y)]));
+            }
+            out.push('\n');
+        }
+
+        out
+    }
 
-    pub(super) fn render_prep(&self) -> RenderState {
+    pub(super) fn render_prep(&self, alpha: f32) -> RenderState {
         // clone region
         let x1 = (self.camera.0 / 16f32).floor() as usize;
         let y1 = (self.camera.1 / 16f32).floor() as usize;
         let x2 = ((self.camera.0 + self.camera.2) / 16f32).ceil() as usize;
         let y2 = ((self.camera.1 + self.camera.3) / 16f32).ceil() as usize;
         let static_block_types = self.static_block_types.clone_sub(x1..x2, y1..y2);
+        let terrain = self.terrain.clone_sub(x1..x2, y1..y2);
+
+        // move sliding blocks partway to where they'll be next tick, short of any block
+        // they're about to stop at
+        let sliding_block_positions: Vec<_> = (0..self.sliding_block_ids.len())
+            .map(|index| {
+                let dir = self.sliding_block_directions[index];
+                let pos = self.sliding_block_positions[index];
+                let to = pos + dir.vec2f() * (SLIDING_BLOCK_SPEED * alpha);
+                let block_type = self.sliding_block_types[index];
+                match sliding_block_stop(
+                    &self.static_block_types,
+                    &self.terrain,
+                    pos,
+                    to,
+                    dir,
+                    block_type,
+                ) {
+                    Some((x, y)) if self.terrain[(x, y)] != Terrain::Water => {
+                        Vec2((x * 16) as f32, (y * 16) as f32)
+                    }
+                    _ => to,
+                }
+            })
+            .collect();
+        let projectile_positions: Vec<_> = (0..self.projectile_ids.len())
+            .map(|index| {
+                let udir = self.projectile_directions[index].vec2f();
+                self.projectile_positions[index] + udir * (self.projectile_speeds[index] * alpha)
+            })
+            .collect();
+
+        // likewise for moving tanks, by rerunning their movement at an in-between time
+        let last_clock = self.clock.saturating_sub(US_PER_TICK);
+        let us_timestamp = last_clock + (alpha * US_PER_TICK as f32) as u64;
+        let mut tank_positions = self.tank_positions.clone();
+        tank_movement(
+            self.tank_ids.len(),
+            us_timestamp,
+            &mut tank_positions,
+            &mut self.tank_directions.clone(),
+            &mut self.tank_states.clone(),
+            &self.static_block_types,
+            &self.terrain,
+        );
+
+        let tank_shielded: Vec<_> = self
+            .tank_shields
+            .iter()
+            .map(|&shield| shield > self.clock)
+            .collect();
+
+        // show what the editor would place under the mouse
+        let editor_cursor = match (&self.editor, self.cursor_cell()) {
+            (Some(editor), Some((x, y))) => {
+                let uv = match editor.brush {
+                    Brush::Block(index) => block_to_uv(BLOCK_PALETTE[index]),
+                    Brush::Tank(_) => tank_to_uv(TankState::Idle),
+                };
+                Some((Vec2((x * 16) as f32, (y * 16) as f32), uv))
+            }
+            _ => None,
+        };
+
+        // round the clock up to whole seconds for the hud
+        let rem_seconds = (self.rem_time + 999_999) / 1_000_000;
+
+        // rattle the view along the shaken wall, dying away
+        let shake = match self.wall_shake {
+            Some((side, timer)) if !timer.done(us_timestamp) => {
+                let strength = 2. * (1. - timer.ratio(us_timestamp));
+                let sign = match timer.elapsed(us_timestamp) / (2 * US_PER_TICK) % 2 {
+                    0 => 1.,
+                    _ => -1.,
+                };
+                side.vec2f() * (strength * sign)
+            }
+            _ => Vec2(0., 0.),
+        };
 
         // temporary frame to test rendering
         RenderState {
             exit: self.exit,
-            time: self.rem_time as u8,
+            game_state: self.game_state,
+            time: rem_seconds.min(255) as u8,
             remaining_tanks: self.rem_tanks,
+            score: self.score,
             camera: self.camera,
 
             static_blocks_offset: Vec2((x1 * 16) as f32, (y1 * 16) as f32),
             static_block_types,
+            terrain,
 
-            sliding_block_positions: self.sliding_block_positions.clone().into_boxed_slice(),
+            sliding_block_positions: sliding_block_positions.into_boxed_slice(),
             sliding_block_types: self.sliding_block_types.clone().into_boxed_slice(),
 
-            tank_positions: self.tank_positions.clone().into_boxed_slice(),
+            projectile_positions: projectile_positions.into_boxed_slice(),
+
+            tank_positions: tank_positions.into_boxed_slice(),
             tank_directions: self.tank_directions.clone().into_boxed_slice(),
             tank_states: self.tank_states.clone().into_boxed_slice(),
+            tank_shielded: tank_shielded.into_boxed_slice(),
+
+            shake,
+
+            editor_cursor,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::event::Key;
+    use super::*;
+
+    #[test]
+    fn stuck_diamond_line_pays_once() {
+        let level = crate::level::Level::parse(
+            "tank 2 2 right player\n\
+             tank 1 3 left patrol l\n\
+             map 5 5\n\
+             #####\n\
+             #..*#\n\
+             #..*#\n\
+             #..*#\n\
+             #####\n",
+        )
+        .unwrap();
+
+        // shove the middle of a finished line into the wall behind it, a few times over
+        let mut state = State::from_level(&level);
+        state.pre_step(Some(Event::KeyDown(Key::Z)).into_iter());
+        for _ in 0..300 {
+            state.step();
+        }
+
+        assert_eq!(state.score, 0);
+        assert!(state.sliding_block_ids.is_empty());
+        assert_eq!(state.static_block_types[(3, 2)], Some(BlockType::Diamond));
+    }
+}